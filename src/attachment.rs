@@ -0,0 +1,200 @@
+use crate::parser::{Body, ContentDispositionHeaderField, MediaType, Parameters};
+use crate::related::Related;
+use crate::sequence::Sequence;
+use crate::walk::{root_section, to_sequence};
+
+/// A part that mail clients would list as an attachment.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Attachment {
+    pub section: Sequence,
+    /// The disposition's filename, else the content type's name, decoded.
+    pub filename: Option<String>,
+    pub media_type: MediaType,
+    /// Size as declared in the BODYSTRUCTURE, still transfer-encoded.
+    pub octets: usize,
+    pub content_transfer_encoding: Vec<u8>,
+    pub disposition: Option<Vec<u8>>,
+    pub content_id: Option<Vec<u8>>,
+}
+
+/// The fields of a single or message/rfc822 part that decide whether it is
+/// an attachment.
+pub(crate) struct Leaf<'a> {
+    pub(crate) media_type: &'a MediaType,
+    pub(crate) parameters: &'a Parameters,
+    pub(crate) disposition: &'a ContentDispositionHeaderField,
+    pub(crate) content_id: Option<&'a [u8]>,
+    pub(crate) location: Option<&'a [u8]>,
+    pub(crate) encoding: &'a [u8],
+    pub(crate) octets: usize,
+}
+
+pub(crate) fn leaf(body: &Body) -> Option<Leaf<'_>> {
+    match body {
+        Body::Single(body) => Some(Leaf {
+            media_type: &body.content_type.ttype,
+            parameters: &body.content_type.parameters,
+            disposition: &body.content_disposition,
+            content_id: body.content_id.value.as_deref(),
+            location: body.content_location.value.as_deref(),
+            encoding: &body.content_transfer_encoding.value,
+            octets: body.octets,
+        }),
+        Body::Message(body) => Some(Leaf {
+            media_type: &body.content_type.ttype,
+            parameters: &body.content_type.parameters,
+            disposition: &body.content_disposition,
+            content_id: body.content_id.value.as_deref(),
+            location: body.content_location.value.as_deref(),
+            encoding: &body.content_transfer_encoding.value,
+            octets: body.octets,
+        }),
+        Body::Multi(_) => None,
+    }
+}
+
+impl Leaf<'_> {
+    pub(crate) fn filename(&self) -> Option<String> {
+        self.disposition
+            .parameters
+            .get_decoded(b"filename")
+            .or_else(|| self.parameters.get_decoded(b"name"))
+            .map(|x| x.value)
+    }
+}
+
+/// Where a part sits: inside a multipart/alternative, and as a non-root
+/// part of a multipart/related, with whether the root refers to it.
+#[derive(Clone, Copy)]
+struct Position {
+    in_alternative: bool,
+    referenced_by_root: Option<bool>,
+}
+
+fn is_attachment(leaf: &Leaf, filename: &Option<String>, position: Position) -> bool {
+    if leaf
+        .disposition
+        .value
+        .as_ref()
+        .is_some_and(|x| x.eq_ignore_ascii_case(b"attachment"))
+    {
+        return true;
+    }
+    if let Some(referenced) = position.referenced_by_root {
+        if referenced {
+            return false;
+        }
+        if leaf.content_id.is_some() || leaf.location.is_some() {
+            // Meant to be shown inline, but never referenced.
+            return true;
+        }
+    }
+    filename.is_some() || (!leaf.media_type.is_text() && !position.in_alternative)
+}
+
+fn collect(section: Vec<usize>, body: &Body, position: Position, attachments: &mut Vec<Attachment>) {
+    let Body::Multi(multi) = body else {
+        // Parts of an attached message belong to that message.
+        let Some(leaf) = leaf(body) else {
+            return;
+        };
+        let filename = leaf.filename();
+        if is_attachment(&leaf, &filename, position) {
+            attachments.push(Attachment {
+                section: to_sequence(section),
+                filename,
+                media_type: leaf.media_type.clone(),
+                octets: leaf.octets,
+                content_transfer_encoding: leaf.encoding.to_vec(),
+                disposition: leaf.disposition.value.clone(),
+                content_id: leaf.content_id.map(<[u8]>::to_vec),
+            });
+        }
+        return;
+    };
+    let in_alternative =
+        position.in_alternative || multi.content_type.is(b"multipart", b"alternative");
+    let related = Related::new(multi);
+    for (index, part) in multi.parts.iter().enumerate() {
+        let mut child = section.clone();
+        child.push(index + 1);
+        let referenced_by_root = related
+            .as_ref()
+            .filter(|x| x.root != index)
+            .map(|x| x.is_referenced(index));
+        let position = Position {
+            in_alternative,
+            referenced_by_root,
+        };
+        collect(child, part, position, attachments);
+    }
+}
+
+impl Body {
+    /// The parts a mail client would list as attachments, in section order:
+    /// parts with an attachment disposition or a file name, and non-text
+    /// parts outside multipart/alternative, except those the root of a
+    /// multipart/related refers to by Content-ID or Content-Location.
+    pub fn attachments(&self) -> Vec<Attachment> {
+        let mut attachments = vec![];
+        let position = Position {
+            in_alternative: false,
+            referenced_by_root: None,
+        };
+        collect(root_section(self), self, position, &mut attachments);
+        attachments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::body_parser;
+
+    #[test]
+    fn test_attachments() {
+        let body = body_parser(br#"((("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 10 1 NIL NIL NIL NIL)(("TEXT" "HTML" ("CHARSET" "utf-8") NIL NIL "7BIT" 40 1 NIL NIL NIL NIL)("IMAGE" "PNG" ("NAME" "logo.png") "<logo@x>" NIL "BASE64" 100 NIL ("inline" NIL) NIL NIL)("IMAGE" "PNG" NIL "<unused@x>" NIL "BASE64" 200 NIL NIL NIL NIL) "RELATED" NIL NIL NIL NIL) "ALTERNATIVE" ("BOUNDARY" "a") NIL NIL NIL)("APPLICATION" "OCTET-STREAM" NIL NIL NIL "BASE64" 7160 NIL ("attachment" ("filename*" "utf-8''05.APC_bind_shell-%E5%AF%86%E7%A0%81.rar")) NIL NIL)("TEXT" "PLAIN" ("NAME" "=?utf-8?B?5a+G56CB?=.txt") NIL NIL "7BIT" 5 1 NIL ("inline" NIL) NIL NIL)("IMAGE" "JPEG" NIL NIL NIL "BASE64" 300 NIL ("inline" NIL) NIL NIL)("TEXT" "PLAIN" NIL NIL NIL "7BIT" 3 1 NIL NIL NIL NIL) "MIXED" ("BOUNDARY" "m") NIL NIL NIL)"#).unwrap().1;
+        let attachments = body.attachments();
+        let sections: Vec<Sequence> = attachments.iter().map(|x| x.section.clone()).collect();
+        let expected: Vec<Sequence> = [b"2".as_ref(), b"3", b"4"]
+            .iter()
+            .map(|x| Sequence::new(x).unwrap())
+            .collect();
+        assert_eq!(sections, expected);
+        assert_eq!(
+            attachments[0],
+            Attachment {
+                section: Sequence::new(b"2").unwrap(),
+                filename: Some("05.APC_bind_shell-密码.rar".to_string()),
+                media_type: MediaType::new(b"application", b"octet-stream"),
+                octets: 7160,
+                content_transfer_encoding: b"BASE64".to_vec(),
+                disposition: Some(b"attachment".to_vec()),
+                content_id: None,
+            }
+        );
+        assert_eq!(attachments[1].filename, Some("密码.txt".to_string()));
+        assert_eq!(attachments[2].media_type, MediaType::new(b"image", b"jpeg"));
+    }
+
+    #[test]
+    fn test_unreferenced_related_parts() {
+        let mut body = body_parser(br#"(("TEXT" "HTML" NIL NIL NIL "BASE64" 40 1 NIL NIL NIL NIL)("IMAGE" "PNG" NIL "<logo@x>" NIL "BASE64" 100 NIL NIL NIL NIL)("IMAGE" "PNG" NIL "<unused@x>" NIL "BASE64" 200 NIL NIL NIL NIL) "RELATED" NIL NIL NIL NIL)"#).unwrap().1;
+        // Without the HTML both images count as referenced.
+        assert_eq!(body.attachments(), vec![]);
+        body.set_data(Sequence::new(b"1").unwrap(), b"PGltZyBzcmM9IkNJRDpsb2dvQHgiPg==".to_vec());
+        let attachments = body.attachments();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].content_id, Some(b"<unused@x>".to_vec()));
+        assert_eq!(attachments[0].section, Sequence::new(b"3").unwrap());
+    }
+
+    #[test]
+    fn test_single_part_attachment() {
+        let body = body_parser(br#"("APPLICATION" "PDF" ("NAME" "a.pdf") NIL NIL "BASE64" 1024 NIL NIL NIL NIL)"#).unwrap().1;
+        let attachments = body.attachments();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].section, Sequence::new(b"1").unwrap());
+        assert_eq!(attachments[0].filename, Some("a.pdf".to_string()));
+    }
+}
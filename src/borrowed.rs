@@ -0,0 +1,452 @@
+//! A BODYSTRUCTURE whose fields borrow from the parsed input.
+//!
+//! Strings are only copied when a quoted string has to be unescaped, so
+//! parsing does no per-field allocation. `into_owned` turns each type into
+//! its counterpart in `parser` or `envelope`.
+//! ```rust
+//! # use imap_bodystructure::borrowed;
+//! let text = br#"("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "8BIT" 393 9 NIL NIL NIL)"#;
+//! let body = borrowed::body_parser(text).unwrap().1;
+//! let borrowed::Body::Single(single) = &body else { unreachable!() };
+//! assert_eq!(single.fields.parameters[0].value.as_ref(), b"utf-8");
+//! assert_eq!(body.into_owned(), imap_bodystructure::parser::body_parser(text).unwrap().1);
+//! ```
+use std::borrow::Cow;
+
+use nom::IResult;
+
+use crate::envelope;
+use crate::error::Error;
+use crate::parser::{self, BodyForm, Context, ParseWarning, ParserOptions};
+
+fn owned(value: Option<Cow<'_, [u8]>>) -> Option<Vec<u8>> {
+    value.map(Cow::into_owned)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Parameter<'a> {
+    pub attribute: Cow<'a, [u8]>,
+    pub value: Cow<'a, [u8]>,
+}
+
+impl Parameter<'_> {
+    pub fn into_owned(self) -> parser::Parameter {
+        parser::Parameter {
+            attribute: self.attribute.into_owned(),
+            value: self.value.into_owned(),
+        }
+    }
+}
+
+fn parameters_into_owned(list: Vec<Parameter<'_>>) -> parser::Parameters {
+    parser::Parameters {
+        list: list.into_iter().map(Parameter::into_owned).collect(),
+    }
+}
+
+/// body-fld-dsp when it is not NIL.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Disposition<'a> {
+    pub value: Cow<'a, [u8]>,
+    pub parameters: Vec<Parameter<'a>>,
+}
+
+pub(crate) fn disposition_into_owned(
+    disposition: Option<Disposition<'_>>,
+) -> parser::ContentDispositionHeaderField {
+    match disposition {
+        Some(disposition) => parser::ContentDispositionHeaderField {
+            value: Some(disposition.value.into_owned()),
+            parameters: parameters_into_owned(disposition.parameters),
+        },
+        None => parser::ContentDispositionHeaderField {
+            value: None,
+            parameters: parser::Parameters { list: vec![] },
+        },
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BodyExtension<'a> {
+    NString(Option<Cow<'a, [u8]>>),
+    Number(u64),
+    List(Vec<BodyExtension<'a>>),
+}
+
+impl BodyExtension<'_> {
+    pub fn into_owned(self) -> parser::BodyExtension {
+        match self {
+            BodyExtension::NString(value) => parser::BodyExtension::NString(owned(value)),
+            BodyExtension::Number(value) => parser::BodyExtension::Number(value),
+            BodyExtension::List(list) => {
+                parser::BodyExtension::List(list.into_iter().map(|x| x.into_owned()).collect())
+            }
+        }
+    }
+}
+
+pub(crate) fn extensions_into_owned(extensions: Vec<BodyExtension<'_>>) -> Vec<parser::BodyExtension> {
+    extensions.into_iter().map(|x| x.into_owned()).collect()
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Address<'a> {
+    pub name: Option<Cow<'a, [u8]>>,
+    pub adl: Option<Cow<'a, [u8]>>,
+    pub mailbox: Option<Cow<'a, [u8]>>,
+    pub host: Option<Cow<'a, [u8]>>,
+}
+
+impl Address<'_> {
+    pub fn into_owned(self) -> envelope::Address {
+        envelope::Address {
+            name: owned(self.name),
+            adl: owned(self.adl),
+            mailbox: owned(self.mailbox),
+            host: owned(self.host),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum AddressEntry<'a> {
+    Mailbox(Address<'a>),
+    Group {
+        name: Cow<'a, [u8]>,
+        members: Vec<Address<'a>>,
+    },
+}
+
+impl AddressEntry<'_> {
+    pub fn into_owned(self) -> envelope::AddressEntry {
+        match self {
+            AddressEntry::Mailbox(address) => envelope::AddressEntry::Mailbox(address.into_owned()),
+            AddressEntry::Group { name, members } => envelope::AddressEntry::Group {
+                name: name.into_owned(),
+                members: members.into_iter().map(Address::into_owned).collect(),
+            },
+        }
+    }
+}
+
+fn addresses_into_owned(addresses: Vec<AddressEntry<'_>>) -> Vec<envelope::AddressEntry> {
+    addresses.into_iter().map(AddressEntry::into_owned).collect()
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Envelope<'a> {
+    pub date: Option<Cow<'a, [u8]>>,
+    pub subject: Option<Cow<'a, [u8]>>,
+    pub from: Vec<AddressEntry<'a>>,
+    pub sender: Vec<AddressEntry<'a>>,
+    pub reply_to: Vec<AddressEntry<'a>>,
+    pub to: Vec<AddressEntry<'a>>,
+    pub cc: Vec<AddressEntry<'a>>,
+    pub bcc: Vec<AddressEntry<'a>>,
+    pub in_reply_to: Option<Cow<'a, [u8]>>,
+    pub message_id: Option<Cow<'a, [u8]>>,
+}
+
+impl Envelope<'_> {
+    pub fn into_owned(self) -> envelope::Envelope {
+        envelope::Envelope {
+            date: owned(self.date),
+            subject: owned(self.subject),
+            from: addresses_into_owned(self.from),
+            sender: addresses_into_owned(self.sender),
+            reply_to: addresses_into_owned(self.reply_to),
+            to: addresses_into_owned(self.to),
+            cc: addresses_into_owned(self.cc),
+            bcc: addresses_into_owned(self.bcc),
+            in_reply_to: owned(self.in_reply_to),
+            message_id: owned(self.message_id),
+        }
+    }
+}
+
+/// The body fields shared by basic, text and message/rfc822 parts.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Fields<'a> {
+    pub ttype: Cow<'a, [u8]>,
+    pub subtype: Cow<'a, [u8]>,
+    pub parameters: Vec<Parameter<'a>>,
+    pub content_id: Option<Cow<'a, [u8]>>,
+    pub content_description: Option<Cow<'a, [u8]>>,
+    pub content_transfer_encoding: Cow<'a, [u8]>,
+}
+
+impl Fields<'_> {
+    fn into_owned(
+        self,
+    ) -> (
+        parser::ContentTypeHeaderField,
+        parser::ContentIDHeaderField,
+        parser::ContentDescriptionHeaderField,
+        parser::ContentTransferEncodingHeaderField,
+    ) {
+        (
+            parser::ContentTypeHeaderField {
+                ttype: parser::ContentTypeTypeAndSubType {
+                    ttype: self.ttype.into_owned(),
+                    subtype: self.subtype.into_owned(),
+                },
+                parameters: parameters_into_owned(self.parameters),
+            },
+            parser::ContentIDHeaderField {
+                value: owned(self.content_id),
+            },
+            parser::ContentDescriptionHeaderField {
+                value: owned(self.content_description),
+            },
+            parser::ContentTransferEncodingHeaderField {
+                value: self.content_transfer_encoding.into_owned(),
+            },
+        )
+    }
+}
+
+/// The extension data shared by all parts; empty in the BODY form.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Extension<'a> {
+    pub content_disposition: Option<Disposition<'a>>,
+    pub content_language: Vec<Cow<'a, [u8]>>,
+    pub content_location: Option<Cow<'a, [u8]>>,
+    pub extensions: Vec<BodyExtension<'a>>,
+}
+
+impl Extension<'_> {
+    fn into_owned(
+        self,
+    ) -> (
+        parser::ContentDispositionHeaderField,
+        parser::ContentLanguageHeaderField,
+        parser::ContentLocationHeaderField,
+        Vec<parser::BodyExtension>,
+    ) {
+        (
+            disposition_into_owned(self.content_disposition),
+            parser::ContentLanguageHeaderField {
+                value: self.content_language.into_iter().map(Cow::into_owned).collect(),
+            },
+            parser::ContentLocationHeaderField {
+                value: owned(self.content_location),
+            },
+            extensions_into_owned(self.extensions),
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SingleBody<'a> {
+    pub fields: Fields<'a>,
+    pub octets: usize,
+    pub lines: Option<u32>,
+    pub form: BodyForm,
+    pub content_md5: Option<Cow<'a, [u8]>>,
+    pub extension: Extension<'a>,
+}
+
+impl SingleBody<'_> {
+    pub fn into_owned(self) -> parser::SingleBody {
+        let (content_type, content_id, content_description, content_transfer_encoding) =
+            self.fields.into_owned();
+        let (content_disposition, content_language, content_location, extensions) =
+            self.extension.into_owned();
+        parser::SingleBody {
+            content_type,
+            content_id,
+            content_description,
+            content_transfer_encoding,
+            octets: self.octets,
+            lines: self.lines,
+            form: self.form,
+            content_md5: parser::ContentMD5HeaderField {
+                value: owned(self.content_md5),
+            },
+            content_disposition,
+            content_language,
+            content_location,
+            extensions,
+            data: vec![],
+            raw_header: vec![],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MessageBody<'a> {
+    pub fields: Fields<'a>,
+    pub octets: usize,
+    pub envelope: Envelope<'a>,
+    pub body: Box<Body<'a>>,
+    pub lines: u32,
+    pub form: BodyForm,
+    pub content_md5: Option<Cow<'a, [u8]>>,
+    pub extension: Extension<'a>,
+}
+
+impl MessageBody<'_> {
+    pub(crate) fn has_extension_data(&self) -> bool {
+        self.form == BodyForm::BodyStructure || self.body.has_extension_data()
+    }
+
+    pub(crate) fn set_form(&mut self, form: BodyForm) {
+        self.form = form;
+        self.body.set_form(form);
+    }
+
+    pub fn into_owned(self) -> parser::MessageBody {
+        let (content_type, content_id, content_description, content_transfer_encoding) =
+            self.fields.into_owned();
+        let (content_disposition, content_language, content_location, extensions) =
+            self.extension.into_owned();
+        parser::MessageBody {
+            content_type,
+            content_id,
+            content_description,
+            content_transfer_encoding,
+            octets: self.octets,
+            envelope: self.envelope.into_owned(),
+            body: Box::new(self.body.into_owned()),
+            lines: self.lines,
+            form: self.form,
+            content_md5: parser::ContentMD5HeaderField {
+                value: owned(self.content_md5),
+            },
+            content_disposition,
+            content_language,
+            content_location,
+            extensions,
+            data: vec![],
+            raw_header: vec![],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MultiBody<'a> {
+    pub parts: Vec<Body<'a>>,
+    pub subtype: Cow<'a, [u8]>,
+    pub form: BodyForm,
+    pub parameters: Vec<Parameter<'a>>,
+    pub extension: Extension<'a>,
+}
+
+impl MultiBody<'_> {
+    pub(crate) fn has_extension_data(&self) -> bool {
+        self.form == BodyForm::BodyStructure || self.parts.iter().any(Body::has_extension_data)
+    }
+
+    pub(crate) fn set_form(&mut self, form: BodyForm) {
+        self.form = form;
+        self.parts.iter_mut().for_each(|x| x.set_form(form));
+    }
+
+    pub fn into_owned(self) -> parser::MultiBody {
+        let (content_disposition, content_language, content_location, extensions) =
+            self.extension.into_owned();
+        parser::MultiBody {
+            parts: self.parts.into_iter().map(Body::into_owned).collect(),
+            content_type: parser::MediaType {
+                ttype: b"multipart".to_vec(),
+                subtype: self.subtype.into_owned(),
+            },
+            form: self.form,
+            parameters: parameters_into_owned(self.parameters),
+            content_disposition,
+            content_language,
+            content_location,
+            extensions,
+            raw_header: vec![],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Body<'a> {
+    Single(SingleBody<'a>),
+    Multi(MultiBody<'a>),
+    Message(MessageBody<'a>),
+}
+
+impl Body<'_> {
+    pub fn into_owned(self) -> parser::Body {
+        match self {
+            Body::Single(single) => parser::Body::Single(single.into_owned()),
+            Body::Multi(multi) => parser::Body::Multi(multi.into_owned()),
+            Body::Message(message) => parser::Body::Message(message.into_owned()),
+        }
+    }
+}
+
+impl Body<'_> {
+    /// Whether any part carries extension data, which only BODYSTRUCTURE has.
+    pub(crate) fn has_extension_data(&self) -> bool {
+        match self {
+            Body::Single(single) => single.form == BodyForm::BodyStructure,
+            Body::Multi(multi) => multi.has_extension_data(),
+            Body::Message(message) => message.has_extension_data(),
+        }
+    }
+
+    /// Marks this body and all its parts as coming from `form`.
+    pub(crate) fn set_form(&mut self, form: BodyForm) {
+        match self {
+            Body::Single(single) => single.form = form,
+            Body::Multi(multi) => multi.set_form(form),
+            Body::Message(message) => message.set_form(form),
+        }
+    }
+}
+
+pub fn body_parser(s: &[u8]) -> IResult<&[u8], Body<'_>> {
+    parser::body_ctx(&Context::strict(s), s)
+}
+
+/// `parser::parse_body` without copying the fields out of `s`.
+pub fn parse_body<'a>(
+    s: &'a [u8],
+    options: &ParserOptions,
+) -> Result<(Body<'a>, Vec<ParseWarning>), Error> {
+    let ctx = Context::new(*options, s);
+    match parser::body_ctx(&ctx, s) {
+        Ok((_, body)) => Ok((body, ctx.into_warnings())),
+        Err(_) => Err(ctx.into_error(s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body_parser_borrows_input() {
+        let text = [
+            br#"(("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1 NIL ("attachment" ("filename" "say \"hi\".txt")) NIL NIL)"#.as_ref(),
+            br#"("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 ("Wed, 17 Jul 1996" {8}"#,
+            b"\r\n",
+            br#"Original ((NIL NIL "gray" "cac.washington.edu")) NIL NIL NIL NIL NIL NIL NIL) ("TEXT" "PLAIN" NIL NIL NIL "7BIT" 8 1) 12) "MIXED" ("BOUNDARY" "b1") NIL NIL NIL)"#,
+        ]
+        .concat();
+        let body = body_parser(&text).unwrap().1;
+        let Body::Multi(multi) = &body else {
+            panic!("expected a multipart body");
+        };
+        let Body::Single(single) = &multi.parts[0] else {
+            panic!("expected a single part");
+        };
+        assert!(matches!(single.fields.ttype, Cow::Borrowed(b"TEXT")));
+        let disposition = single.extension.content_disposition.as_ref().unwrap();
+        // Only strings with escapes are copied.
+        assert!(matches!(disposition.parameters[0].value, Cow::Owned(_)));
+        assert_eq!(disposition.parameters[0].value.as_ref(), br#"say "hi".txt"#);
+        let Body::Message(message) = &multi.parts[1] else {
+            panic!("expected a message part");
+        };
+        assert!(matches!(message.envelope.subject, Some(Cow::Borrowed(b"Original"))));
+        assert!(matches!(&message.envelope.from[0], AddressEntry::Mailbox(x) if x.mailbox.as_deref() == Some(b"gray".as_ref())));
+
+        assert_eq!(body.into_owned(), parser::body_parser(&text).unwrap().1);
+    }
+}
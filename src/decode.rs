@@ -0,0 +1,295 @@
+use std::borrow::Cow;
+
+use encoding_rs::Encoding;
+
+/// A parameter value decoded per RFC 2231, with the charset and language
+/// given in its first section.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DecodedParameter {
+    pub value: String,
+    pub charset: Option<Vec<u8>>,
+    pub language: Option<Vec<u8>>,
+}
+
+/// Decodes `bytes` in the named charset, or None if the charset is unknown.
+/// Labels follow the WHATWG list, so GB2312 is read as its GBK superset.
+pub fn charset_decode(charset: &[u8], bytes: &[u8]) -> Option<String> {
+    let encoding = Encoding::for_label(charset.trim_ascii())?;
+    Some(encoding.decode_without_bom_handling(bytes).0.into_owned())
+}
+
+/// Like `charset_decode`, but falls back to lossy UTF-8.
+pub(crate) fn decode_or_lossy(charset: Option<&[u8]>, bytes: &[u8]) -> String {
+    charset
+        .and_then(|x| charset_decode(x, bytes))
+        .unwrap_or_else(|| String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn hex_value(s: u8) -> Option<u8> {
+    (s as char).to_digit(16).map(|x| x as u8)
+}
+
+/// Decodes %XX escapes; a "%" not followed by two hex digits is kept as is.
+pub fn percent_decode(s: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == b'%' && i + 2 < s.len() {
+            if let (Some(high), Some(low)) = (hex_value(s[i + 1]), hex_value(s[i + 2])) {
+                result.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        result.push(s[i]);
+        i += 1;
+    }
+    result
+}
+
+/// Joins the sections of an RFC 2231 parameter, given in order as the value
+/// and whether it was extended ("name*N*"). Only an extended first section
+/// carries charset'language'.
+pub(crate) fn decode_rfc2231(sections: &[(&[u8], bool)]) -> DecodedParameter {
+    let mut charset = None;
+    let mut language = None;
+    let mut bytes = vec![];
+    for (index, (value, extended)) in sections.iter().enumerate() {
+        if !extended {
+            bytes.extend_from_slice(value);
+            continue;
+        }
+        let mut value: &[u8] = value;
+        if index == 0 {
+            let mut fields = value.splitn(3, |x| *x == b'\'');
+            if let (Some(first), Some(second), Some(rest)) = (fields.next(), fields.next(), fields.next()) {
+                charset = Some(first.to_vec()).filter(|x| !x.is_empty());
+                language = Some(second.to_vec()).filter(|x| !x.is_empty());
+                value = rest;
+            }
+        }
+        bytes.append(&mut percent_decode(value));
+    }
+    DecodedParameter {
+        value: decode_or_lossy(charset.as_deref(), &bytes),
+        charset,
+        language,
+    }
+}
+
+fn base64_value(s: u8) -> Option<u8> {
+    match s {
+        b'A'..=b'Z' => Some(s - b'A'),
+        b'a'..=b'z' => Some(s - b'a' + 26),
+        b'0'..=b'9' => Some(s - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decodes base64, tolerating missing padding; None on any other character.
+fn base64_decode(s: &[u8]) -> Option<Vec<u8>> {
+    let s = s.strip_suffix(b"==").or_else(|| s.strip_suffix(b"=")).unwrap_or(s);
+    let mut result = Vec::with_capacity(s.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for x in s {
+        buffer = buffer << 6 | base64_value(*x)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+/// Decodes quoted-printable (RFC 2045 section 6.7), dropping soft line breaks.
+fn quoted_printable_decode(s: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == b'=' {
+            if s[i + 1..].starts_with(b"\r\n") {
+                i += 3;
+                continue;
+            }
+            if s[i + 1..].starts_with(b"\n") {
+                i += 2;
+                continue;
+            }
+            if i + 2 < s.len() {
+                if let (Some(high), Some(low)) = (hex_value(s[i + 1]), hex_value(s[i + 2])) {
+                    result.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+            }
+        }
+        result.push(s[i]);
+        i += 1;
+    }
+    result
+}
+
+/// Undoes a Content-Transfer-Encoding. Data that is not valid base64, and
+/// encodings other than base64 and quoted-printable, come back as they are.
+pub fn decode_transfer_encoding<'a>(encoding: &[u8], data: &'a [u8]) -> Cow<'a, [u8]> {
+    if encoding.eq_ignore_ascii_case(b"base64") {
+        let text: Vec<u8> = data.iter().copied().filter(|x| !x.is_ascii_whitespace()).collect();
+        match base64_decode(&text) {
+            Some(decoded) => Cow::Owned(decoded),
+            None => Cow::Borrowed(data),
+        }
+    } else if encoding.eq_ignore_ascii_case(b"quoted-printable") {
+        Cow::Owned(quoted_printable_decode(data))
+    } else {
+        Cow::Borrowed(data)
+    }
+}
+
+/// Decodes the Q encoding of RFC 2047 section 4.2.
+fn q_decode(s: &[u8]) -> Vec<u8> {
+    let s: Vec<u8> = s.iter().map(|x| if *x == b'_' { b' ' } else { *x }).collect();
+    let mut result = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == b'=' && i + 2 < s.len() {
+            if let (Some(high), Some(low)) = (hex_value(s[i + 1]), hex_value(s[i + 2])) {
+                result.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        result.push(s[i]);
+        i += 1;
+    }
+    result
+}
+
+/// Reads an encoded word "=?charset?E?text?=" at the start of `s`, returning
+/// its length, the charset without any RFC 2231 "*language" and the bytes.
+fn encoded_word(s: &[u8]) -> Option<(usize, &[u8], Vec<u8>)> {
+    let rest = s.strip_prefix(b"=?")?;
+    let charset_end = rest.iter().position(|x| *x == b'?')?;
+    let charset = &rest[..charset_end];
+    let rest = &rest[charset_end + 1..];
+    if rest.len() < 2 || rest[1] != b'?' {
+        return None;
+    }
+    let text = &rest[2..];
+    let text_end = text.windows(2).position(|x| x == b"?=")?;
+    let text = &text[..text_end];
+    if text.iter().any(|x| x.is_ascii_whitespace()) {
+        return None;
+    }
+    let bytes = match rest[0].to_ascii_uppercase() {
+        b'B' => base64_decode(text)?,
+        b'Q' => q_decode(text),
+        _ => return None,
+    };
+    let charset = charset.split(|x| *x == b'*').next().unwrap_or(charset);
+    Some((charset_end + text_end + 7, charset, bytes))
+}
+
+/// Decodes the RFC 2047 encoded words in header text. Whitespace between
+/// adjacent encoded words is dropped, and adjacent words in one charset are
+/// joined before decoding so a character split across them survives.
+/// Unknown charsets fall back to lossy UTF-8; malformed words are kept as is.
+pub fn decode_encoded_words(s: &[u8]) -> String {
+    let mut result = String::new();
+    // Charset and bytes of the encoded words not yet decoded.
+    let mut pending: Option<(&[u8], Vec<u8>)> = None;
+    let mut i = 0;
+    while i < s.len() {
+        if let Some((length, charset, mut bytes)) = encoded_word(&s[i..]) {
+            match &mut pending {
+                Some((pending_charset, pending_bytes)) if pending_charset.eq_ignore_ascii_case(charset) => {
+                    pending_bytes.append(&mut bytes);
+                }
+                _ => {
+                    if let Some((charset, bytes)) = pending.take() {
+                        result.push_str(&decode_or_lossy(Some(charset), &bytes));
+                    }
+                    pending = Some((charset, bytes));
+                }
+            }
+            i += length;
+            // Only whitespace up to the next encoded word is dropped.
+            let blank = s[i..].iter().take_while(|x| x.is_ascii_whitespace()).count();
+            if blank > 0 && encoded_word(&s[i + blank..]).is_some() {
+                i += blank;
+            }
+            continue;
+        }
+        if let Some((charset, bytes)) = pending.take() {
+            result.push_str(&decode_or_lossy(Some(charset), &bytes));
+        }
+        let next = s[i + 1..]
+            .windows(2)
+            .position(|x| x == b"=?")
+            .map_or(s.len(), |x| i + 1 + x);
+        result.push_str(&String::from_utf8_lossy(&s[i..next]));
+        i = next;
+    }
+    if let Some((charset, bytes)) = pending {
+        result.push_str(&decode_or_lossy(Some(charset), &bytes));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode(b"a%20b%zz%4"), b"a b%zz%4".to_vec());
+        assert_eq!(percent_decode(b"%E5%AF%86"), "密".as_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_charset_decode() {
+        assert_eq!(charset_decode(b"GB2312", b"\xc4\xe3\xba\xc3"), Some("你好".to_string()));
+        assert_eq!(charset_decode(b"gbk", b"\xc4\xe3"), Some("你".to_string()));
+        assert_eq!(charset_decode(b"x-unknown", b"abc"), None);
+        assert_eq!(decode_or_lossy(Some(b"x-unknown"), b"abc"), "abc");
+    }
+
+    #[test]
+    fn test_decode_rfc2231() {
+        let decoded = decode_rfc2231(&[(b"utf-8''05.APC_bind_shell-%E5%AF%86%E7%A0%81.rar", true)]);
+        assert_eq!(decoded.value, "05.APC_bind_shell-密码.rar");
+        assert_eq!(decoded.charset, Some(b"utf-8".to_vec()));
+        assert_eq!(decoded.language, None);
+        let decoded = decode_rfc2231(&[(b"gb2312'zh-cn'%C4%E3", true), (b"%BA%C3", true), (b".txt", false)]);
+        assert_eq!(decoded.value, "你好.txt");
+        assert_eq!(decoded.language, Some(b"zh-cn".to_vec()));
+        assert_eq!(decode_rfc2231(&[(b"a%20", false), (b"b", false)]).value, "a%20b");
+    }
+
+    #[test]
+    fn test_decode_encoded_words() {
+        assert_eq!(
+            decode_encoded_words(b"=?utf-8?b?5L2g5aW9IDBiMGZiYjZkYmFmM2FmYmIgenFhLWVtYWls5rWL6K+V?="),
+            "你好 0b0fbb6dbaf3afbb zqa-email测试"
+        );
+        assert_eq!(decode_encoded_words(b"=?ISO-8859-1?Q?Andr=E9_?= Pirard"), "André  Pirard");
+        assert_eq!(decode_encoded_words(b"(=?ISO-8859-1?Q?a?= \r\n =?ISO-8859-1?Q?b?=)"), "(ab)");
+        assert_eq!(decode_encoded_words(b"=?gb2312?B?xOM=?= =?GB2312?B?usM=?=.txt"), "你好.txt");
+        // One character split across two words.
+        assert_eq!(decode_encoded_words(b"=?utf-8?Q?=E5=AF?= =?utf-8?Q?=86?="), "密");
+        assert_eq!(decode_encoded_words(b"=?x-unknown?Q?abc?="), "abc");
+        assert_eq!(decode_encoded_words(b"=?utf-8?B?@@@?= plain =? text"), "=?utf-8?B?@@@?= plain =? text");
+        assert_eq!(decode_encoded_words(b"=?utf-8*en?Q?hi?="), "hi");
+    }
+
+    #[test]
+    fn test_decode_transfer_encoding() {
+        assert_eq!(decode_transfer_encoding(b"BASE64", b"PGltZyBz\r\ncmM9ImNpZDph\r\nIj4="), b"<img src=\"cid:a\">".as_ref());
+        assert_eq!(decode_transfer_encoding(b"quoted-printable", b"src=3D\"ci=\r\nd:a\"=20"), b"src=\"cid:a\" ".as_ref());
+        assert_eq!(decode_transfer_encoding(b"7bit", b"a=20"), b"a=20".as_ref());
+        assert_eq!(decode_transfer_encoding(b"base64", b"@@"), b"@@".as_ref());
+    }
+}
@@ -0,0 +1,141 @@
+use crate::attachment::leaf;
+use crate::parser::{Body, MultiBody};
+use crate::related::related_root;
+use crate::sequence::Sequence;
+use crate::walk::{root_section, to_sequence};
+
+/// Subtype the body would show as text, for ranking the alternatives of a
+/// multipart/alternative.
+fn display_subtype<'a>(body: &'a Body, prefer: &[&[u8]]) -> Option<&'a [u8]> {
+    match body {
+        Body::Single(single) if is_inline_text(body) => Some(&single.content_type.ttype.subtype),
+        Body::Single(_) | Body::Message(_) => None,
+        Body::Multi(multi) => {
+            let subtype = multi.content_type.subtype.as_slice();
+            if subtype.eq_ignore_ascii_case(b"related") {
+                display_subtype(multi.parts.get(related_root(multi))?, prefer)
+            } else if subtype.eq_ignore_ascii_case(b"alternative") {
+                display_subtype(&multi.parts[best_alternative(multi, prefer)?], prefer)
+            } else {
+                multi.parts.iter().find_map(|x| display_subtype(x, prefer))
+            }
+        }
+    }
+}
+
+/// Rank of a subtype, lower is better; subtypes not in `prefer` come last.
+fn rank(subtype: &[u8], prefer: &[&[u8]]) -> usize {
+    prefer
+        .iter()
+        .position(|x| x.eq_ignore_ascii_case(subtype))
+        .unwrap_or(prefer.len())
+}
+
+/// The alternative to show: the best ranked, and of equals the last, since
+/// RFC 2046 orders alternatives from plainest to richest.
+fn best_alternative(multi: &MultiBody, prefer: &[&[u8]]) -> Option<usize> {
+    multi
+        .parts
+        .iter()
+        .enumerate()
+        .filter_map(|(index, part)| display_subtype(part, prefer).map(|x| (rank(x, prefer), index)))
+        .min_by_key(|(rank, index)| (*rank, usize::MAX - index))
+        .map(|(_, index)| index)
+}
+
+/// A text part that is not an attachment.
+fn is_inline_text(body: &Body) -> bool {
+    let Some(leaf) = leaf(body) else {
+        return false;
+    };
+    let attached = leaf
+        .disposition
+        .value
+        .as_ref()
+        .is_some_and(|x| x.eq_ignore_ascii_case(b"attachment"));
+    leaf.media_type.is_text() && !attached && leaf.filename().is_none()
+}
+
+fn select(section: Vec<usize>, body: &Body, prefer: &[&[u8]], sections: &mut Vec<Sequence>) {
+    let numbered = |index: usize| {
+        let mut child = section.clone();
+        child.push(index);
+        child
+    };
+    match body {
+        Body::Single(_) => {
+            if is_inline_text(body) {
+                sections.push(to_sequence(section));
+            }
+        }
+        Body::Message(message) => {
+            // Only a message meant to be shown inline is read into.
+            let inline = message
+                .content_disposition
+                .value
+                .as_ref()
+                .is_some_and(|x| x.eq_ignore_ascii_case(b"inline"));
+            if inline {
+                match message.body.as_ref() {
+                    inner @ Body::Multi(_) => select(section, inner, prefer, sections),
+                    inner => select(numbered(1), inner, prefer, sections),
+                }
+            }
+        }
+        Body::Multi(multi) => {
+            let subtype = multi.content_type.subtype.as_slice();
+            let chosen: Vec<usize> = if subtype.eq_ignore_ascii_case(b"alternative") {
+                best_alternative(multi, prefer).into_iter().collect()
+            } else if subtype.eq_ignore_ascii_case(b"related") {
+                vec![related_root(multi)]
+            } else {
+                (0..multi.parts.len()).collect()
+            };
+            for index in chosen {
+                if let Some(part) = multi.parts.get(index) {
+                    select(numbered(index + 1), part, prefer, sections);
+                }
+            }
+        }
+    }
+}
+
+impl Body {
+    /// Sections of the text a reader sees, in display order, to fetch with
+    /// `BODY.PEEK[section]`. `prefer` lists text subtypes from most to least
+    /// wanted, e.g. `&[b"plain", b"html"]`, and picks one alternative of each
+    /// multipart/alternative. Of a multipart/related only the root is read,
+    /// and every inline text part of a multipart/mixed is kept.
+    pub fn displayable_parts(&self, prefer: &[&[u8]]) -> Vec<Sequence> {
+        let mut sections = vec![];
+        select(root_section(self), self, prefer, &mut sections);
+        sections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::body_parser;
+
+    fn sections(list: &[&[u8]]) -> Vec<Sequence> {
+        list.iter().map(|x| Sequence::new(x).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_displayable_parts() {
+        let body = body_parser(br#"((("TEXT" "PLAIN" NIL NIL NIL "7BIT" 10 1)(("TEXT" "HTML" NIL NIL NIL "7BIT" 40 1)("IMAGE" "PNG" NIL "<logo@x>" NIL "BASE64" 100) "RELATED") "ALTERNATIVE")("APPLICATION" "PDF" ("NAME" "a.pdf") NIL NIL "BASE64" 1024)("TEXT" "PLAIN" NIL NIL NIL "7BIT" 3 1)("TEXT" "PLAIN" ("NAME" "notes.txt") NIL NIL "7BIT" 3 1)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 100 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) ("TEXT" "PLAIN" NIL NIL NIL "7BIT" 8 1) 5 NIL ("inline" NIL) NIL NIL) "MIXED")"#).unwrap().1;
+        assert_eq!(body.displayable_parts(&[b"html", b"plain"]), sections(&[b"1.2.1", b"3", b"5.1"]));
+        assert_eq!(body.displayable_parts(&[b"plain"]), sections(&[b"1.1", b"3", b"5.1"]));
+        // Without a preference the last, richest alternative wins.
+        assert_eq!(body.displayable_parts(&[]), sections(&[b"1.2.1", b"3", b"5.1"]));
+    }
+
+    #[test]
+    fn test_displayable_single_part() {
+        let body = body_parser(br#"("TEXT" "HTML" NIL NIL NIL "7BIT" 10 1)"#).unwrap().1;
+        assert_eq!(body.displayable_parts(&[b"plain"]), sections(&[b"1"]));
+        let body = body_parser(br#"("IMAGE" "PNG" NIL NIL NIL "BASE64" 10)"#).unwrap().1;
+        assert_eq!(body.displayable_parts(&[b"plain"]), vec![]);
+    }
+}
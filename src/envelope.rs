@@ -0,0 +1,386 @@
+use std::borrow::Cow;
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case},
+    combinator::map,
+    multi::many1,
+    sequence::{delimited, tuple},
+    IResult,
+};
+
+use crate::borrowed;
+use crate::decode::decode_encoded_words;
+use crate::error::{Construct, Error};
+use crate::parser::{self, nstring_ctx as nstring, sp, Context};
+
+/// RFC 3501 address: (addr-name addr-adl addr-mailbox addr-host)
+#[derive(Debug, PartialEq)]
+pub struct Address {
+    pub name: Option<Vec<u8>>,
+    pub adl: Option<Vec<u8>>,
+    pub mailbox: Option<Vec<u8>>,
+    pub host: Option<Vec<u8>>,
+}
+
+impl Address {
+    /// The display name with RFC 2047 encoded words decoded.
+    pub fn decoded_name(&self) -> Option<String> {
+        self.name.as_deref().map(decode_encoded_words)
+    }
+
+    pub fn get_text(&self) -> Vec<u8> {
+        let mut result: Vec<u8> = vec![];
+        if let Some(mailbox) = &self.mailbox {
+            result.extend_from_slice(mailbox);
+        }
+        if let Some(host) = &self.host {
+            result.push(b'@');
+            result.extend_from_slice(host);
+        }
+        if let Some(name) = &self.name {
+            // RFC 5322 specials force the display name into a quoted-string.
+            let mut named = if name.iter().any(|x| b"()<>[]:;@\\,.\"".contains(x)) {
+                let mut quoted_name = b"\"".to_vec();
+                quoted_name.append(&mut parser::escape_quoted(name));
+                quoted_name.push(b'"');
+                quoted_name
+            } else {
+                name.clone()
+            };
+            named.extend_from_slice(b" <");
+            named.append(&mut result);
+            named.push(b'>');
+            return named;
+        }
+        result
+    }
+}
+
+pub fn address_parser(s: &[u8]) -> IResult<&[u8], Address> {
+    let (remain, address) = address_ctx(&Context::strict(s), s)?;
+    Ok((remain, address.into_owned()))
+}
+
+fn address_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], borrowed::Address<'a>> {
+    map(
+        delimited(
+            tag(b"("),
+            tuple((
+                |i| nstring(ctx, i),
+                |i| sp(ctx, i),
+                |i| nstring(ctx, i),
+                |i| sp(ctx, i),
+                |i| nstring(ctx, i),
+                |i| sp(ctx, i),
+                |i| nstring(ctx, i),
+            )),
+            tag(b")"),
+        ),
+        |(name, _, adl, _, mailbox, _, host)| borrowed::Address {
+            name,
+            adl,
+            mailbox,
+            host,
+        },
+    )(s)
+}
+
+/// An entry of an address list: RFC 5322 address = mailbox / group
+#[derive(Debug, PartialEq)]
+pub enum AddressEntry {
+    Mailbox(Address),
+    Group { name: Vec<u8>, members: Vec<Address> },
+}
+
+impl AddressEntry {
+    pub fn get_text(&self) -> Vec<u8> {
+        match self {
+            AddressEntry::Mailbox(address) => address.get_text(),
+            AddressEntry::Group { name, members } => {
+                let mut result = name.clone();
+                result.extend_from_slice(b": ");
+                let members: Vec<Vec<u8>> = members.iter().map(|x| x.get_text()).collect();
+                result.append(&mut members.join(b", ".as_ref()));
+                result.push(b';');
+                result
+            }
+        }
+    }
+}
+
+pub fn address_list_parser(s: &[u8]) -> IResult<&[u8], Vec<AddressEntry>> {
+    let (remain, list) = address_list_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
+        list.into_iter().map(borrowed::AddressEntry::into_owned).collect(),
+    ))
+}
+
+fn address_list_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], Vec<borrowed::AddressEntry<'a>>> {
+    alt((
+        map(tag_no_case(b"NIL"), |_| vec![]),
+        map(
+            delimited(tag(b"("), many1(|i| address_ctx(ctx, i)), tag(b")")),
+            group_addresses,
+        ),
+    ))(s)
+}
+
+/// RFC 3501 marks the start of a group with a NIL host and the group name as
+/// mailbox, and its end with a NIL mailbox and host.
+fn group_addresses(addresses: Vec<borrowed::Address<'_>>) -> Vec<borrowed::AddressEntry<'_>> {
+    let mut entries = vec![];
+    let mut group: Option<(Cow<'_, [u8]>, Vec<borrowed::Address<'_>>)> = None;
+    for address in addresses {
+        if address.host.is_some() {
+            match &mut group {
+                Some((_, members)) => members.push(address),
+                None => entries.push(borrowed::AddressEntry::Mailbox(address)),
+            }
+        } else if let Some(name) = address.mailbox {
+            if let Some((name, members)) = group.take() {
+                entries.push(borrowed::AddressEntry::Group { name, members });
+            }
+            group = Some((name, vec![]));
+        } else if let Some((name, members)) = group.take() {
+            entries.push(borrowed::AddressEntry::Group { name, members });
+        }
+    }
+    // A group whose end marker is missing ends with the list.
+    if let Some((name, members)) = group {
+        entries.push(borrowed::AddressEntry::Group { name, members });
+    }
+    entries
+}
+
+/// RFC 3501 section 7.4.2
+#[derive(Debug, PartialEq)]
+pub struct Envelope {
+    pub date: Option<Vec<u8>>,
+    pub subject: Option<Vec<u8>>,
+    pub from: Vec<AddressEntry>,
+    pub sender: Vec<AddressEntry>,
+    pub reply_to: Vec<AddressEntry>,
+    pub to: Vec<AddressEntry>,
+    pub cc: Vec<AddressEntry>,
+    pub bcc: Vec<AddressEntry>,
+    pub in_reply_to: Option<Vec<u8>>,
+    pub message_id: Option<Vec<u8>>,
+}
+
+fn header_line(name: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut result = name.to_vec();
+    result.extend_from_slice(b": ");
+    result.extend_from_slice(value);
+    result.extend_from_slice(b"\r\n");
+    result
+}
+
+fn address_header_line(name: &[u8], addresses: &[AddressEntry]) -> Vec<u8> {
+    if addresses.is_empty() {
+        return vec![];
+    }
+    let value = addresses
+        .iter()
+        .map(|x| x.get_text())
+        .collect::<Vec<Vec<u8>>>()
+        .join(b", ".as_ref());
+    header_line(name, &value)
+}
+
+impl Envelope {
+    /// The subject with RFC 2047 encoded words decoded.
+    pub fn decoded_subject(&self) -> Option<String> {
+        self.subject.as_deref().map(decode_encoded_words)
+    }
+
+    /// Header lines that can be rebuilt from the envelope, each ending with CRLF.
+    pub fn get_text(&self) -> Vec<u8> {
+        let mut result: Vec<u8> = vec![];
+        if let Some(date) = &self.date {
+            result.append(&mut header_line(b"Date", date));
+        }
+        if let Some(subject) = &self.subject {
+            result.append(&mut header_line(b"Subject", subject));
+        }
+        result.append(&mut address_header_line(b"From", &self.from));
+        result.append(&mut address_header_line(b"Sender", &self.sender));
+        result.append(&mut address_header_line(b"Reply-To", &self.reply_to));
+        result.append(&mut address_header_line(b"To", &self.to));
+        result.append(&mut address_header_line(b"Cc", &self.cc));
+        result.append(&mut address_header_line(b"Bcc", &self.bcc));
+        if let Some(in_reply_to) = &self.in_reply_to {
+            result.append(&mut header_line(b"In-Reply-To", in_reply_to));
+        }
+        if let Some(message_id) = &self.message_id {
+            result.append(&mut header_line(b"Message-ID", message_id));
+        }
+        result
+    }
+}
+
+pub fn envelope_parser(s: &[u8]) -> IResult<&[u8], Envelope> {
+    let (remain, envelope) = envelope_ctx(&Context::strict(s), s)?;
+    Ok((remain, envelope.into_owned()))
+}
+
+pub(crate) fn envelope_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], borrowed::Envelope<'a>> {
+    let nstring = |i| nstring(ctx, i);
+    let sp = |i| sp(ctx, i);
+    let address_list = |i| address_list_ctx(ctx, i);
+    map(
+        delimited(
+            tag(b"("),
+            tuple((
+                tuple((nstring, sp, nstring, sp)),
+                tuple((address_list, sp, address_list, sp, address_list, sp)),
+                tuple((address_list, sp, address_list, sp, address_list, sp)),
+                tuple((nstring, sp, nstring)),
+            )),
+            tag(b")"),
+        ),
+        |(
+            (date, _, subject, _),
+            (from, _, sender, _, reply_to, _),
+            (to, _, cc, _, bcc, _),
+            (in_reply_to, _, message_id),
+        )| borrowed::Envelope {
+            date,
+            subject,
+            from,
+            sender,
+            reply_to,
+            to,
+            cc,
+            bcc,
+            in_reply_to,
+            message_id,
+        },
+    )(s)
+}
+
+/// `envelope_parser` with the failure as an `Error`.
+pub fn parse_envelope(s: &[u8]) -> Result<Envelope, Error> {
+    let ctx = Context::strict(s);
+    let result = ctx.field(Construct::Envelope, s, |i| envelope_ctx(&ctx, i));
+    match result {
+        Ok((_, envelope)) => Ok(envelope.into_owned()),
+        Err(_) => Err(ctx.into_error(s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_parser() {
+        assert_eq!(
+            address_parser(br#"("Terry Gray" NIL "gray" "cac.washington.edu")"#)
+                .unwrap()
+                .1,
+            Address {
+                name: Some(b"Terry Gray".to_vec()),
+                adl: None,
+                mailbox: Some(b"gray".to_vec()),
+                host: Some(b"cac.washington.edu".to_vec()),
+            }
+        );
+        assert_eq!(
+            address_parser(br#"("Terry Gray" NIL "gray" "cac.washington.edu")"#)
+                .unwrap()
+                .1
+                .get_text(),
+            b"Terry Gray <gray@cac.washington.edu>"
+        );
+        assert_eq!(
+            address_parser(br#"(NIL NIL "imap" "cac.washington.edu")"#)
+                .unwrap()
+                .1
+                .get_text(),
+            b"imap@cac.washington.edu"
+        );
+        assert_eq!(
+            address_parser(br#"("Gray, \"Terry\"" NIL "gray" "cac.washington.edu")"#)
+                .unwrap()
+                .1
+                .get_text(),
+            br#""Gray, \"Terry\"" <gray@cac.washington.edu>"#
+        );
+    }
+
+    #[test]
+    fn test_envelope_parser() {
+        let envelope = envelope_parser(br#"("Wed, 17 Jul 1996 02:23:25 -0700 (PDT)" "IMAP4rev1 WG mtg summary and minutes" (("Terry Gray" NIL "gray" "cac.washington.edu")) (("Terry Gray" NIL "gray" "cac.washington.edu")) (("Terry Gray" NIL "gray" "cac.washington.edu")) ((NIL NIL "imap" "cac.washington.edu")) ((NIL NIL "minutes" "CNRI.Reston.VA.US")("John Klensin" NIL "KLENSIN" "MIT.EDU")) NIL NIL "<B27397-0100000@cac.washington.edu>")"#)
+            .unwrap()
+            .1;
+        assert_eq!(envelope.subject, Some(b"IMAP4rev1 WG mtg summary and minutes".to_vec()));
+        assert_eq!(envelope.cc.len(), 2);
+        assert_eq!(envelope.bcc, vec![]);
+        assert_eq!(envelope.in_reply_to, None);
+        assert_eq!(
+            envelope.to,
+            vec![AddressEntry::Mailbox(Address {
+                name: None,
+                adl: None,
+                mailbox: Some(b"imap".to_vec()),
+                host: Some(b"cac.washington.edu".to_vec()),
+            })]
+        );
+        assert_eq!(
+            envelope.get_text(),
+            b"Date: Wed, 17 Jul 1996 02:23:25 -0700 (PDT)\r\nSubject: IMAP4rev1 WG mtg summary and minutes\r\nFrom: Terry Gray <gray@cac.washington.edu>\r\nSender: Terry Gray <gray@cac.washington.edu>\r\nReply-To: Terry Gray <gray@cac.washington.edu>\r\nTo: imap@cac.washington.edu\r\nCc: minutes@CNRI.Reston.VA.US, John Klensin <KLENSIN@MIT.EDU>\r\nMessage-ID: <B27397-0100000@cac.washington.edu>\r\n"
+        );
+    }
+
+    #[test]
+    fn test_decoded_envelope_text() {
+        let envelope = envelope_parser(br#"(NIL "=?utf-8?Q?Caf=C3=A9?= menu" (("=?gb2312?B?xOO6ww==?=" NIL "a" "example.com")) NIL NIL NIL NIL NIL NIL NIL)"#)
+            .unwrap()
+            .1;
+        assert_eq!(envelope.decoded_subject(), Some("Café menu".to_string()));
+        let AddressEntry::Mailbox(from) = &envelope.from[0] else {
+            panic!("expected a mailbox");
+        };
+        assert_eq!(from.decoded_name(), Some("你好".to_string()));
+    }
+
+    #[test]
+    fn test_address_list_groups() {
+        let list = address_list_parser(br#"((NIL NIL "team" NIL)("Ann" NIL "ann" "example.com")(NIL NIL "bob" "example.com")(NIL NIL NIL NIL)(NIL NIL "carol" "example.com")(NIL NIL "undisclosed-recipients" NIL)(NIL NIL NIL NIL))"#)
+            .unwrap()
+            .1;
+        assert_eq!(list.len(), 3);
+        let AddressEntry::Group { name, members } = &list[0] else {
+            panic!("expected a group");
+        };
+        assert_eq!(name, b"team");
+        assert_eq!(members.len(), 2);
+        assert!(matches!(&list[1], AddressEntry::Mailbox(x) if x.mailbox == Some(b"carol".to_vec())));
+        assert_eq!(
+            list[2],
+            AddressEntry::Group {
+                name: b"undisclosed-recipients".to_vec(),
+                members: vec![],
+            }
+        );
+        assert_eq!(
+            address_header_line(b"To", &list),
+            b"To: team: Ann <ann@example.com>, bob@example.com;, carol@example.com, undisclosed-recipients: ;\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_envelope_error() {
+        let error = parse_envelope(br#"("Wed, 17 Jul 1996" "Subject" (("Terry" NIL "gray" "cac.washington.edu") NIL NIL NIL NIL NIL NIL NIL)"#).unwrap_err();
+        assert!(matches!(error, Error::Body { construct: Construct::Envelope, .. }));
+        assert!(parse_envelope(br#"(NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL)"#).is_ok());
+    }
+}
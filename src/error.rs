@@ -0,0 +1,157 @@
+use std::fmt;
+
+/// Number of input bytes kept in an error, starting at its offset.
+const SNIPPET_LEN: usize = 32;
+
+/// The part of a BODYSTRUCTURE that could not be parsed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Construct {
+    /// The parenthesised body itself, e.g. a missing "(" or ")".
+    Body,
+    ContentType,
+    ContentId,
+    ContentDescription,
+    ContentTransferEncoding,
+    Octets,
+    ContentMD5,
+    ContentDisposition,
+    ContentLanguage,
+    ContentLocation,
+    Extension,
+    Envelope,
+    /// body-fld-lines of a text or message/rfc822 part.
+    Lines,
+    MultipartSubtype,
+    MultipartParameters,
+}
+
+impl fmt::Display for Construct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Construct::Body => "body",
+            Construct::ContentType => "content type",
+            Construct::ContentId => "content id",
+            Construct::ContentDescription => "description",
+            Construct::ContentTransferEncoding => "transfer encoding",
+            Construct::Octets => "size",
+            Construct::ContentMD5 => "md5",
+            Construct::ContentDisposition => "disposition",
+            Construct::ContentLanguage => "language",
+            Construct::ContentLocation => "location",
+            Construct::Extension => "extension data",
+            Construct::Envelope => "envelope",
+            Construct::Lines => "line count",
+            Construct::MultipartSubtype => "multipart subtype",
+            Construct::MultipartParameters => "multipart parameters",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A BODYSTRUCTURE or BODY could not be parsed. `section` is the part
+    /// number of the failing part (RFC 3501 6.4.5), empty for an outermost
+    /// multipart, and `offset` counts from the start of the parsed body text.
+    Body {
+        construct: Construct,
+        section: Vec<usize>,
+        offset: usize,
+        snippet: Vec<u8>,
+    },
+    /// A FETCH response could not be split or read.
+    Response { offset: usize, snippet: Vec<u8> },
+    /// A section part specifier is not a dot-separated list of numbers.
+    Sequence { offset: usize, snippet: Vec<u8> },
+}
+
+/// Up to `SNIPPET_LEN` bytes of `input` from `offset` on.
+pub(crate) fn snippet(input: &[u8], offset: usize) -> Vec<u8> {
+    let start = offset.min(input.len());
+    let end = (start + SNIPPET_LEN).min(input.len());
+    input[start..end].to_vec()
+}
+
+/// Offset of the remaining input of a nom error within `input`.
+pub(crate) fn nom_offset(input: &[u8], err: &nom::Err<nom::error::Error<&[u8]>>) -> usize {
+    match err {
+        nom::Err::Error(e) | nom::Err::Failure(e) => input.len().saturating_sub(e.input.len()),
+        nom::Err::Incomplete(_) => input.len(),
+    }
+}
+
+impl Error {
+    pub fn offset(&self) -> usize {
+        match self {
+            Error::Body { offset, .. }
+            | Error::Response { offset, .. }
+            | Error::Sequence { offset, .. } => *offset,
+        }
+    }
+
+    pub fn snippet(&self) -> &[u8] {
+        match self {
+            Error::Body { snippet, .. }
+            | Error::Response { snippet, .. }
+            | Error::Sequence { snippet, .. } => snippet,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Body {
+                construct, section, ..
+            } => {
+                write!(f, "invalid {} of ", construct)?;
+                if section.is_empty() {
+                    f.write_str("the outermost body")?;
+                } else {
+                    let section = section
+                        .iter()
+                        .map(|x| x.to_string())
+                        .collect::<Vec<String>>()
+                        .join(".");
+                    write!(f, "part {}", section)?;
+                }
+            }
+            Error::Response { .. } => f.write_str("invalid FETCH response")?,
+            Error::Sequence { .. } => f.write_str("invalid section part")?,
+        }
+        write!(
+            f,
+            " at offset {} near {:?}",
+            self.offset(),
+            String::from_utf8_lossy(self.snippet())
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_display() {
+        let error = Error::Body {
+            construct: Construct::ContentDisposition,
+            section: vec![2, 1],
+            offset: 120,
+            snippet: b"(\"attachment\" (\"filename\")".to_vec(),
+        };
+        assert_eq!(
+            error.to_string(),
+            r#"invalid disposition of part 2.1 at offset 120 near "(\"attachment\" (\"filename\")""#
+        );
+        let error = Error::Sequence {
+            offset: 0,
+            snippet: snippet(b"HEADER", 0),
+        };
+        assert_eq!(error.to_string(), r#"invalid section part at offset 0 near "HEADER""#);
+        assert_eq!(snippet(&[b'a'; 40], 4).len(), SNIPPET_LEN);
+        assert_eq!(snippet(b"abc", 5), b"".to_vec());
+    }
+}
@@ -1,4 +1,6 @@
+use crate::error::{self, Error};
 use crate::sequence::{self, Sequence};
+use std::str::from_utf8;
 use nom::{
     branch::alt,
     bytes::complete::{tag, tag_no_case, take_until, take_while},
@@ -6,7 +8,7 @@ use nom::{
         complete::{alphanumeric1, digit1},
         is_digit,
     },
-    combinator::{map, opt},
+    combinator::{map, map_res, opt},
     error::ErrorKind,
     multi::many1,
     sequence::{delimited, terminated, tuple},
@@ -68,7 +70,7 @@ pub(crate) fn fetch_all_body_parser(s: &[u8]) -> IResult<&[u8], ()> {
 }
 
 pub fn uid_fetch_body_parser(s: &[u8]) -> IResult<&[u8], UidFetch> {
-    map(
+    map_res(
         tuple((
             alphanumeric1,
             tag(b" "),
@@ -78,13 +80,15 @@ pub fn uid_fetch_body_parser(s: &[u8]) -> IResult<&[u8], UidFetch> {
             opt(tag_no_case(b".PEEK")),
             delimited(
                 tag(b"["),
-                take_while(|x| is_digit(x) || x == b'.' as u8),
+                take_while(|x| is_digit(x) || x == b'.'),
                 tag(b"]"),
             ),
         )),
-        |(_, _, _, uid, _, _, seq)| UidFetch {
-            sequence: Sequence::new(seq).unwrap(),
-            uid: uid.to_vec(),
+        |(_, _, _, uid, _, _, seq)| {
+            Sequence::new(seq).map(|sequence| UidFetch {
+                sequence,
+                uid: uid.to_vec(),
+            })
         },
     )(s)
 }
@@ -135,20 +139,153 @@ pub fn split_multi_fetch_response_parser(
     }
 }
 
+/// `split_multi_fetch_response_parser` with the failure as an `Error`.
+pub fn split_multi_fetch_response(
+    s: &[u8],
+    include_first_line: bool,
+) -> Result<(&[u8], Vec<Vec<u8>>), Error> {
+    split_multi_fetch_response_parser(s, include_first_line).map_err(|e| {
+        let offset = error::nom_offset(s, &e);
+        Error::Response {
+            offset,
+            snippet: error::snippet(s, offset),
+        }
+    })
+}
+
 pub fn ascii_lowercase_equal(vec1: &[u8], vec2: &[u8]) -> bool {
     vec1.iter()
         .map(|&b| b.to_ascii_lowercase())
         .eq(vec2.iter().map(|&b| b.to_ascii_lowercase()))
 }
 
-pub fn extract_bodystructure(origin_vec: &Vec<u8>) -> Vec<u8> {
+/// Returns the index just past the quoted string starting at `start`,
+/// or the end of `s` if the string is never closed.
+fn skip_quoted(s: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < s.len() {
+        match s[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    s.len()
+}
+
+/// Returns the index just past the literal whose "{" is at `start`, or None
+/// if the bytes at `start` are not a literal prefix.
+fn skip_literal(s: &[u8], start: usize) -> Option<usize> {
+    let digits = s[start + 1..].iter().take_while(|x| x.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+    let length = from_utf8(&s[start + 1..start + 1 + digits])
+        .ok()?
+        .parse::<usize>()
+        .ok()?;
+    let mut i = start + 1 + digits;
+    if s.get(i) == Some(&b'+') {
+        i += 1;
+    }
+    if s.get(i..i + 3) != Some(b"}\r\n".as_ref()) {
+        return None;
+    }
+    Some((i + 3 + length).min(s.len()))
+}
+
+/// Returns the index just past the parenthesised list whose "(" is at
+/// `start`, or None if the list is never closed.
+fn skip_list(s: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < s.len() {
+        match s[i] {
+            b'"' => {
+                i = skip_quoted(s, i);
+                continue;
+            }
+            b'{' => {
+                if let Some(end) = skip_literal(s, i) {
+                    i = end;
+                    continue;
+                }
+            }
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Extracts the parenthesised ENVELOPE of a FETCH response, without the
+/// "ENVELOPE " prefix.
+pub fn extract_envelope(origin_vec: &[u8]) -> Option<&[u8]> {
+    let mut token_start = None;
+    let mut index = 0;
+    while index < origin_vec.len() {
+        let i = origin_vec[index];
+        if i == b'"' {
+            index = skip_quoted(origin_vec, index);
+            token_start = None;
+            continue;
+        }
+        if i == b'{' {
+            if let Some(end) = skip_literal(origin_vec, index) {
+                index = end;
+                token_start = None;
+                continue;
+            }
+        }
+        if i.is_ascii_alphabetic() {
+            token_start.get_or_insert(index);
+        } else {
+            let is_envelope = token_start
+                .is_some_and(|start| ascii_lowercase_equal(&origin_vec[start..index], b"ENVELOPE"));
+            if is_envelope && i == b' ' && origin_vec.get(index + 1) == Some(&b'(') {
+                let end = skip_list(origin_vec, index + 1)?;
+                return Some(&origin_vec[index + 1..end]);
+            }
+            token_start = None;
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Extracts the BODYSTRUCTURE, or the non-extensible BODY, item of a FETCH response.
+pub fn extract_bodystructure(origin_vec: &[u8]) -> Vec<u8> {
     let mut token: Vec<u8> = Vec::new();
     let mut bodystructure: Vec<u8> = Vec::new();
     let mut recording = false;
     let mut brackets_count = 0;
+    let mut index = 0;
 
-    for &i in origin_vec {
+    while index < origin_vec.len() {
+        let i = origin_vec[index];
         if recording {
+            // Quoted strings and literals are copied whole, so parentheses
+            // inside them are not counted.
+            if i == b'"' {
+                let end = skip_quoted(origin_vec, index);
+                bodystructure.extend_from_slice(&origin_vec[index..end]);
+                index = end;
+                continue;
+            }
+            if i == b'{' {
+                if let Some(end) = skip_literal(origin_vec, index) {
+                    bodystructure.extend_from_slice(&origin_vec[index..end]);
+                    index = end;
+                    continue;
+                }
+            }
             bodystructure.push(i);
             if i == b'(' {
                 brackets_count += 1;
@@ -158,26 +295,224 @@ pub fn extract_bodystructure(origin_vec: &Vec<u8>) -> Vec<u8> {
                     break;
                 }
             }
+        } else if i == b'"' {
+            // Skipped whole, so an item name inside a string or literal,
+            // e.g. in header data, is not taken for the real one.
+            index = skip_quoted(origin_vec, index);
+            token.clear();
+            continue;
+        } else if let Some(end) = (i == b'{').then(|| skip_literal(origin_vec, index)).flatten() {
+            index = end;
+            token.clear();
+            continue;
+        } else if i.is_ascii_alphabetic() {
+            token.push(i);
         } else {
-            if i.is_ascii_alphabetic() {
-                token.push(i);
-            } else {
-                if ascii_lowercase_equal(&token, b"BODYSTRUCTURE") {
-                    recording = true;
+            // BODY is only the non-extensible structure when a list follows,
+            // BODY[section] carries message data instead.
+            let is_body = ascii_lowercase_equal(&token, b"BODY")
+                && i == b' '
+                && origin_vec.get(index + 1) == Some(&b'(');
+            if is_body || ascii_lowercase_equal(&token, b"BODYSTRUCTURE") {
+                recording = true;
+                if is_body {
+                    bodystructure.extend_from_slice(b"BODY");
+                } else {
                     bodystructure.extend_from_slice(b"BODYSTRUCTURE");
-                    bodystructure.push(i);
-                    if i == b'(' {
-                        brackets_count += 1;
-                    }
                 }
-                token.clear();
+                bodystructure.push(i);
+                if i == b'(' {
+                    brackets_count += 1;
+                }
             }
+            token.clear();
         }
+        index += 1;
     }
 
     bodystructure
 }
 
+/// Result of feeding bytes to a streaming scanner.
+#[derive(Debug, PartialEq)]
+pub enum Streaming<T> {
+    /// The input ran out first; feed the next bytes to go on.
+    NeedMoreData,
+    /// The value, and how many bytes of the last chunk it took.
+    Done(T, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScanState {
+    Normal,
+    Quoted { escaped: bool },
+    /// Inside "{N" or "{N+" of a literal; `length` is None until a digit is
+    /// read, and `plus` is set once the "+" is.
+    LiteralLength { length: Option<usize>, plus: bool },
+    LiteralCr { length: usize },
+    LiteralLf { length: usize },
+    Literal { remaining: usize },
+    /// After "BODY " or "BODYSTRUCTURE ", waiting for the "(" of the list.
+    AfterKeyword { is_body: bool },
+}
+
+/// `extract_bodystructure` for a FETCH response that arrives in pieces.
+/// Each call to `feed` goes on where the last one stopped, so no byte is
+/// scanned twice. It is done at the end of the BODYSTRUCTURE, or BODY,
+/// item, or at the end of a response without one, with an empty item.
+///
+/// ```rust
+/// # use imap_bodystructure::extractor::{BodyStructureScanner, Streaming};
+/// let mut scanner = BodyStructureScanner::new();
+/// assert_eq!(scanner.feed(br#"* 1 FETCH (UID 7 BODYSTRUCTURE ("TEXT" "PLAIN" NIL"#), Streaming::NeedMoreData);
+/// assert_eq!(
+///     scanner.feed(br#" NIL NIL "7BIT" 12 1))"#),
+///     Streaming::Done(br#"BODYSTRUCTURE ("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1)"#.to_vec(), 21)
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct BodyStructureScanner {
+    state: ScanState,
+    token: Vec<u8>,
+    item: Vec<u8>,
+    depth: usize,
+}
+
+impl Default for BodyStructureScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BodyStructureScanner {
+    pub fn new() -> Self {
+        BodyStructureScanner {
+            state: ScanState::Normal,
+            token: vec![],
+            item: vec![],
+            depth: 0,
+        }
+    }
+
+    /// Scans the next bytes of the response. After `Done` the scanner starts
+    /// over, so the rest of the chunk can be fed for the next response.
+    pub fn feed(&mut self, data: &[u8]) -> Streaming<Vec<u8>> {
+        for (index, x) in data.iter().enumerate() {
+            if let Some(item) = self.step(*x) {
+                *self = Self::new();
+                return Streaming::Done(item, index + 1);
+            }
+        }
+        Streaming::NeedMoreData
+    }
+
+    fn recording(&self) -> bool {
+        self.depth > 0
+    }
+
+    fn step(&mut self, x: u8) -> Option<Vec<u8>> {
+        if self.recording() {
+            self.item.push(x);
+        }
+        match self.state {
+            ScanState::Quoted { escaped } => {
+                self.state = match x {
+                    b'"' if !escaped => ScanState::Normal,
+                    b'\\' if !escaped => ScanState::Quoted { escaped: true },
+                    _ => ScanState::Quoted { escaped: false },
+                };
+                return None;
+            }
+            ScanState::Literal { remaining } => {
+                self.state = if remaining > 1 {
+                    ScanState::Literal { remaining: remaining - 1 }
+                } else {
+                    ScanState::Normal
+                };
+                return None;
+            }
+            ScanState::LiteralLength { length, plus } => match (x, length) {
+                (b'0'..=b'9', _) if !plus => {
+                    let digit = (x - b'0') as usize;
+                    // A length too large to count is not taken as a literal,
+                    // as `skip_literal` does.
+                    self.state = match length.unwrap_or(0).checked_mul(10).and_then(|n| n.checked_add(digit)) {
+                        Some(length) => ScanState::LiteralLength { length: Some(length), plus },
+                        None => ScanState::Normal,
+                    };
+                    return None;
+                }
+                (b'+', Some(_)) if !plus => {
+                    self.state = ScanState::LiteralLength { length, plus: true };
+                    return None;
+                }
+                (b'}', Some(length)) => {
+                    self.state = ScanState::LiteralCr { length };
+                    return None;
+                }
+                // Not a literal after all.
+                _ => self.state = ScanState::Normal,
+            },
+            ScanState::LiteralCr { length } if x == b'\r' => {
+                self.state = ScanState::LiteralLf { length };
+                return None;
+            }
+            ScanState::LiteralLf { length } if x == b'\n' => {
+                self.state = if length > 0 {
+                    ScanState::Literal { remaining: length }
+                } else {
+                    ScanState::Normal
+                };
+                return None;
+            }
+            ScanState::LiteralCr { .. } | ScanState::LiteralLf { .. } => self.state = ScanState::Normal,
+            ScanState::AfterKeyword { is_body } => {
+                self.state = ScanState::Normal;
+                if x == b'(' {
+                    self.item = if is_body { b"BODY (".to_vec() } else { b"BODYSTRUCTURE (".to_vec() };
+                    self.depth = 1;
+                    return None;
+                }
+            }
+            ScanState::Normal => {}
+        }
+        match x {
+            b'"' => self.state = ScanState::Quoted { escaped: false },
+            b'{' => {
+                self.state = ScanState::LiteralLength {
+                    length: None,
+                    plus: false,
+                }
+            }
+            b'(' if self.recording() => self.depth += 1,
+            b')' if self.recording() => {
+                self.depth -= 1;
+                if self.depth == 0 {
+                    return Some(std::mem::take(&mut self.item));
+                }
+            }
+            // A line break outside a literal ends the response.
+            b'\n' if !self.recording() => return Some(vec![]),
+            _ => {}
+        }
+        if !self.recording() {
+            if x.is_ascii_alphabetic() {
+                self.token.push(x);
+            } else {
+                if x == b' ' {
+                    if ascii_lowercase_equal(&self.token, b"BODY") {
+                        self.state = ScanState::AfterKeyword { is_body: true };
+                    } else if ascii_lowercase_equal(&self.token, b"BODYSTRUCTURE") {
+                        self.state = ScanState::AfterKeyword { is_body: false };
+                    }
+                }
+                self.token.clear();
+            }
+        }
+        None
+    }
+}
+
 pub fn extract_fetch_respone_all_context_parser(s: &[u8]) -> IResult<&[u8], &[u8]> {
     delimited(tag(b"*"), take_until("\r\n)\r\n"), tag(b"\r\n)\r\n"))(s)
 }
@@ -250,6 +585,18 @@ mod tests {
                 uid: b"303416".to_vec()
             }
         );
+        assert!(uid_fetch_body_parser(b"a5 uid fetch 303416 body[]").is_err());
+    }
+    #[test]
+    fn test_split_multi_fetch_response_error() {
+        let error = split_multi_fetch_response(b"a1 OK done\r\n", true).unwrap_err();
+        assert_eq!(
+            error,
+            Error::Response {
+                offset: 0,
+                snippet: b"a1 OK done\r\n".to_vec()
+            }
+        );
     }
     #[test]
     fn test_find_uid_in_response() {
@@ -278,7 +625,7 @@ mod tests {
     fn extract_bodystructure_test_1() {
         let target = br#"BODYSTRUCTURE ((("TEXT" "HTML" ("charset" "gbk") NIL NIL "BASE64" 140 2 NIL NIL NIL) "RELATED" ("BOUNDARY" "----=_Part_28035_897908132.1699414214660") NIL NIL) "MIXED" ("BOUNDARY" "----=_Part_28034_578039922.1699414214660") NIL NIL)"#;
         let text = br#"* 10 FETCH (BODYSTRUCTURE ((("TEXT" "HTML" ("charset" "gbk") NIL NIL "BASE64" 140 2 NIL NIL NIL) "RELATED" ("BOUNDARY" "----=_Part_28035_897908132.1699414214660") NIL NIL) "MIXED" ("BOUNDARY" "----=_Part_28034_578039922.1699414214660") NIL NIL))"#;
-        let bodystructure = extract_bodystructure(&text.to_vec());
+        let bodystructure = extract_bodystructure(text.as_ref());
         assert_eq!(bodystructure, target);
     }
     #[test]
@@ -289,7 +636,7 @@ mod tests {
         From: liutianyu@nextcloud.games
         To: shenzongxu@nextcloud.games
         Date: Tue, 05 Dec 2023 06:16:58 -0000"#;
-        let bodystructure = extract_bodystructure(&text.to_vec());
+        let bodystructure = extract_bodystructure(text.as_ref());
         assert_eq!(bodystructure, target);
     }
     #[test]
@@ -393,4 +740,88 @@ mod tests {
             Some(5)
         );
     }
+    #[test]
+    fn extract_bodystructure_with_literal() {
+        let target = b"BODYSTRUCTURE (\"application\" \"pdf\" (\"name\" {9}\r\na (b).pdf) NIL NIL \"base64\" 1024 NIL (\"attachment\" (\"filename\" \"c (d).pdf\")) NIL NIL)";
+        let mut text = b"* 3 FETCH (UID 7 ".to_vec();
+        text.extend_from_slice(target);
+        text.extend_from_slice(b" FLAGS ())");
+        let bodystructure = extract_bodystructure(&text);
+        assert_eq!(bodystructure, target);
+    }
+    #[test]
+    fn extract_bodystructure_with_literal8() {
+        let target = b"BODYSTRUCTURE (\"text\" \"plain\" (\"charset\" \"utf-8\") NIL ~{4}\r\n)))) \"7bit\" 10 1 NIL NIL NIL NIL)";
+        let mut text = b"* 3 FETCH (".to_vec();
+        text.extend_from_slice(target);
+        text.extend_from_slice(b")");
+        assert_eq!(extract_bodystructure(&text), target);
+    }
+    #[test]
+    fn extract_bodystructure_after_header_literal() {
+        let text = b"* 1 FETCH (BODY[HEADER] {25}\r\nX: BODYSTRUCTURE (\"a\"\r\n\r\n BODYSTRUCTURE (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 1 1))";
+        assert_eq!(
+            extract_bodystructure(text),
+            b"BODYSTRUCTURE (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 1 1)"
+        );
+        assert_eq!(extract_bodystructure(b"* 1 FETCH (X-ITEM \"BODYSTRUCTURE (\")"), b"");
+    }
+    #[test]
+    fn extract_body_non_extensible() {
+        let text = br#"* 12 FETCH (UID 7 BODY[HEADER.FIELDS (SUBJECT)] {4}
+abc
+ BODY (("TEXT" "PLAIN" ("CHARSET" "US-ASCII") NIL NIL "7BIT" 1152 23)("TEXT" "PLAIN" ("CHARSET" "US-ASCII" "NAME" "cc.diff") "<960723163407.20117h@cac.washington.edu>" "Compiler diff" "BASE64" 4554 73) "MIXED"))"#;
+        assert_eq!(
+            extract_bodystructure(text.as_ref()),
+            br#"BODY (("TEXT" "PLAIN" ("CHARSET" "US-ASCII") NIL NIL "7BIT" 1152 23)("TEXT" "PLAIN" ("CHARSET" "US-ASCII" "NAME" "cc.diff") "<960723163407.20117h@cac.washington.edu>" "Compiler diff" "BASE64" 4554 73) "MIXED")"#
+        );
+    }
+    #[test]
+    fn test_extract_envelope() {
+        let text = b"* 4 FETCH (UID 9 ENVELOPE (NIL {14}\r\nENVELOPE (a)) (\"x (y\" NIL NIL NIL) NIL NIL NIL NIL NIL NIL NIL NIL) FLAGS ())";
+        assert_eq!(
+            extract_envelope(text),
+            Some(b"(NIL {14}\r\nENVELOPE (a)) (\"x (y\" NIL NIL NIL) NIL NIL NIL NIL NIL NIL NIL NIL)".as_ref())
+        );
+        assert_eq!(extract_envelope(b"* 4 FETCH (UID 9 BODY[] \"ENVELOPE (\")"), None);
+        assert_eq!(extract_envelope(b"* 4 FETCH (ENVELOPE (NIL"), None);
+    }
+    #[test]
+    fn test_body_structure_scanner() {
+        let text = b"* 3 FETCH (UID 7 BODY[HEADER] {30}\r\nSubject: BODYSTRUCTURE (\"a\r\n\r\n BODYSTRUCTURE (\"application\" \"pdf\" (\"name\" {9}\r\na (b).pdf) NIL NIL \"base64\" 1024 NIL (\"attachment\" (\"filename\" \"c (d).pdf\")) NIL NIL) FLAGS ())\r\n* 4 FETCH";
+        let target = b"BODYSTRUCTURE (\"application\" \"pdf\" (\"name\" {9}\r\na (b).pdf) NIL NIL \"base64\" 1024 NIL (\"attachment\" (\"filename\" \"c (d).pdf\")) NIL NIL)";
+        // Every split point, including one byte at a time.
+        for size in 1..text.len() {
+            let mut scanner = BodyStructureScanner::new();
+            let mut result = Streaming::NeedMoreData;
+            let mut fed = 0;
+            for chunk in text.chunks(size) {
+                result = scanner.feed(chunk);
+                if let Streaming::Done(_, used) = result {
+                    fed += used;
+                    break;
+                }
+                fed += chunk.len();
+            }
+            assert_eq!(result, Streaming::Done(target.to_vec(), (fed - 1) % size + 1));
+            assert_eq!(&text[fed..fed + 8], b" FLAGS (");
+        }
+        let mut scanner = BodyStructureScanner::new();
+        assert_eq!(scanner.feed(b"* 1 FETCH (UID 7 BODY[] {2}\r\n\r\n"), Streaming::NeedMoreData);
+        assert_eq!(scanner.feed(b")\r\n* 2"), Streaming::Done(vec![], 3));
+        let mut scanner = BodyStructureScanner::new();
+        assert_eq!(
+            scanner.feed(br#"* 12 FETCH (BODY (("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1 1) "MIXED"))"#),
+            Streaming::Done(br#"BODY (("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1 1) "MIXED")"#.to_vec(), 66)
+        );
+        // Neither "{}" nor a length past usize::MAX starts a literal, so the
+        // line break after them ends the response.
+        for brace in [b"{}".as_ref(), b"{99999999999999999999999}"] {
+            let mut text = b"* 1 FETCH (X ".to_vec();
+            text.extend(brace);
+            text.extend(b"\r\n BODYSTRUCTURE (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 1 1))\r\n");
+            let mut scanner = BodyStructureScanner::new();
+            assert_eq!(scanner.feed(&text), Streaming::Done(vec![], 15 + brace.len()));
+        }
+    }
 }
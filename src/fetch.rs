@@ -0,0 +1,260 @@
+use std::fmt;
+
+use crate::parser::{Body, SingleBody, TopLevelType};
+use crate::sequence::Sequence;
+
+/// Which parts of a message to download.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FetchPolicy {
+    /// Only text/* parts.
+    pub text_only: bool,
+    pub skip_images: bool,
+    /// Skip parts whose declared size is larger.
+    pub max_octets: Option<usize>,
+    /// Fetch parts larger than this in `<origin.count>` ranges of this size.
+    pub chunk_octets: Option<usize>,
+    /// Also fetch the MIME header of each part.
+    pub mime_headers: bool,
+}
+
+/// One `BODY.PEEK[...]` item of a FETCH command.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FetchItem {
+    pub section: Sequence,
+    /// The part's MIME header rather than its content.
+    pub mime: bool,
+    /// Origin and count of a partial fetch.
+    pub partial: Option<(usize, usize)>,
+}
+
+/// Writes the item as sent, e.g. `BODY.PEEK[1.2]<0.1024>`.
+impl fmt::Display for FetchItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BODY.PEEK[{}", self.section)?;
+        if self.mime {
+            f.write_str(".MIME")?;
+        }
+        f.write_str("]")?;
+        if let Some((origin, count)) = self.partial {
+            write!(f, "<{}.{}>", origin, count)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_wanted(single: &SingleBody, policy: &FetchPolicy) -> bool {
+    let top_level = single.media_type().top_level();
+    !single.is_with_data()
+        && (!policy.text_only || top_level == TopLevelType::Text)
+        && (!policy.skip_images || top_level != TopLevelType::Image)
+        && policy.max_octets.is_none_or(|max| single.octets <= max)
+}
+
+/// Items to fetch the leaf parts of `body` that `policy` allows and that
+/// have no data yet, in section order. A part of an encapsulated message
+/// is fetched by its own section; the MIME header of the outermost body is
+/// part of the message header and is not fetched.
+pub fn fetch_items(body: &Body, policy: &FetchPolicy) -> Vec<FetchItem> {
+    let mut items = vec![];
+    for (section, part) in body.parts() {
+        let Body::Single(single) = part else {
+            continue;
+        };
+        if !is_wanted(single, policy) {
+            continue;
+        }
+        if policy.mime_headers && !std::ptr::eq(part, body) {
+            items.push(FetchItem {
+                section: section.clone(),
+                mime: true,
+                partial: None,
+            });
+        }
+        match policy.chunk_octets {
+            Some(chunk) if chunk > 0 && single.octets > chunk => {
+                for origin in (0..single.octets).step_by(chunk) {
+                    items.push(FetchItem {
+                        section: section.clone(),
+                        mime: false,
+                        partial: Some((origin, chunk)),
+                    });
+                }
+            }
+            _ => items.push(FetchItem {
+                section,
+                mime: false,
+                partial: None,
+            }),
+        }
+    }
+    items
+}
+
+fn command(uids: &[&[u8]], items: &[FetchItem]) -> Vec<u8> {
+    let mut result = b"UID FETCH ".to_vec();
+    result.extend(uids.join(&b","[..]));
+    let items: Vec<String> = items.iter().map(|x| x.to_string()).collect();
+    if items.len() == 1 {
+        result.push(b' ');
+        result.extend(items[0].as_bytes());
+    } else {
+        result.extend(format!(" ({})", items.join(" ")).as_bytes());
+    }
+    result
+}
+
+/// `UID FETCH` commands, without tag or CRLF, for the items wanted of each
+/// message by UID. Only messages wanting exactly the same items share a
+/// command: merging one list into a longer one would download sections a
+/// message did not ask for. No command is longer than `max_line` octets,
+/// except one for a single UID and a single item.
+pub fn uid_fetch_commands(requests: &[(&[u8], Vec<FetchItem>)], max_line: usize) -> Vec<Vec<u8>> {
+    let mut groups: Vec<(Vec<&[u8]>, &[FetchItem])> = vec![];
+    for (uid, items) in requests {
+        if items.is_empty() {
+            continue;
+        }
+        match groups.iter_mut().find(|(_, x)| *x == items.as_slice()) {
+            Some((uids, _)) => uids.push(uid),
+            None => groups.push((vec![uid], items)),
+        }
+    }
+    let mut commands = vec![];
+    for (uids, items) in groups {
+        // Items are split so that each run fits with any one of the UIDs,
+        // then the UIDs so that each set fits with the run.
+        let longest = uids.iter().copied().max_by_key(|x| x.len()).unwrap_or_default();
+        let mut start = 0;
+        while start < items.len() {
+            let mut end = start + 1;
+            while end < items.len() && command(&[longest], &items[start..end + 1]).len() <= max_line {
+                end += 1;
+            }
+            let run = &items[start..end];
+            let mut uid_set: Vec<&[u8]> = vec![];
+            for &uid in &uids {
+                uid_set.push(uid);
+                if uid_set.len() > 1 && command(&uid_set, run).len() > max_line {
+                    uid_set.pop();
+                    commands.push(command(&uid_set, run));
+                    uid_set = vec![uid];
+                }
+            }
+            commands.push(command(&uid_set, run));
+            start = end;
+        }
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractor::{uid_fetch_body_parser, UidFetch};
+    use crate::parser::body_parser;
+
+    const BODY: &[u8] = br#"((("TEXT" "PLAIN" NIL NIL NIL "7BIT" 10 1)("TEXT" "HTML" NIL NIL NIL "7BIT" 2500 1) "ALTERNATIVE")("IMAGE" "PNG" NIL NIL NIL "BASE64" 100)("APPLICATION" "PDF" NIL NIL NIL "BASE64" 9000000) "MIXED")"#;
+
+    fn item_texts(items: &[FetchItem]) -> Vec<String> {
+        items.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_fetch_items() {
+        let body = body_parser(BODY).unwrap().1;
+        let policy = FetchPolicy {
+            skip_images: true,
+            max_octets: Some(5 * 1024 * 1024),
+            chunk_octets: Some(1024),
+            ..Default::default()
+        };
+        assert_eq!(
+            item_texts(&fetch_items(&body, &policy)),
+            vec![
+                "BODY.PEEK[1.1]",
+                "BODY.PEEK[1.2]<0.1024>",
+                "BODY.PEEK[1.2]<1024.1024>",
+                "BODY.PEEK[1.2]<2048.1024>",
+            ]
+        );
+        let policy = FetchPolicy {
+            text_only: true,
+            mime_headers: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            item_texts(&fetch_items(&body, &policy)),
+            vec!["BODY.PEEK[1.1.MIME]", "BODY.PEEK[1.1]", "BODY.PEEK[1.2.MIME]", "BODY.PEEK[1.2]"]
+        );
+        let single = body_parser(br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 10 1)"#).unwrap().1;
+        assert_eq!(item_texts(&fetch_items(&single, &policy)), vec!["BODY.PEEK[1]"]);
+    }
+
+    #[test]
+    fn test_skip_fetched_parts() {
+        let mut body = body_parser(BODY).unwrap().1;
+        body.set_data(Sequence::new(b"1.1").unwrap(), b"0123456789".to_vec());
+        let policy = FetchPolicy {
+            text_only: true,
+            ..Default::default()
+        };
+        assert_eq!(item_texts(&fetch_items(&body, &policy)), vec!["BODY.PEEK[1.2]"]);
+    }
+
+    #[test]
+    fn test_uid_fetch_commands() {
+        let body = body_parser(BODY).unwrap().1;
+        let text_only = FetchPolicy {
+            text_only: true,
+            ..Default::default()
+        };
+        let small = FetchPolicy {
+            max_octets: Some(100),
+            ..Default::default()
+        };
+        let text = fetch_items(&body, &text_only);
+        let requests = vec![
+            (b"7".as_ref(), text.clone()),
+            (b"9".as_ref(), fetch_items(&body, &small)),
+            (b"12".as_ref(), text.clone()),
+            (b"13".as_ref(), vec![]),
+        ];
+        let commands = uid_fetch_commands(&requests, 8192);
+        assert_eq!(
+            commands,
+            vec![
+                b"UID FETCH 7,12 (BODY.PEEK[1.1] BODY.PEEK[1.2])".to_vec(),
+                b"UID FETCH 9 (BODY.PEEK[1.1] BODY.PEEK[2])".to_vec(),
+            ]
+        );
+        // Too long a line splits the items, and a single item parses back.
+        let commands = uid_fetch_commands(&[(b"696".as_ref(), text.clone())], 30);
+        assert_eq!(commands.len(), 2);
+        let mut line = b"22 ".to_vec();
+        line.extend(&commands[1]);
+        assert_eq!(
+            uid_fetch_body_parser(&line).unwrap().1,
+            UidFetch {
+                uid: b"696".to_vec(),
+                sequence: Sequence::new(b"1.2").unwrap(),
+            }
+        );
+        // Many UIDs with a long item split the UIDs as well.
+        let item = FetchItem {
+            section: Sequence::new(b"1.2").unwrap(),
+            mime: false,
+            partial: Some((2048, 1024)),
+        };
+        let uids: Vec<Vec<u8>> = (100..140).map(|x| x.to_string().into_bytes()).collect();
+        let requests: Vec<(&[u8], Vec<FetchItem>)> = uids.iter().map(|x| (x.as_slice(), vec![item.clone()])).collect();
+        let commands = uid_fetch_commands(&requests, 60);
+        assert!(commands.iter().all(|x| x.len() <= 60));
+        let mut sent: Vec<&[u8]> = vec![];
+        for command in &commands {
+            let uid_set = command[b"UID FETCH ".len()..].split(|x| *x == b' ').next().unwrap();
+            sent.extend(uid_set.split(|x| *x == b','));
+        }
+        assert!(commands.len() > 1);
+        assert_eq!(sent, uids.iter().map(|x| x.as_slice()).collect::<Vec<&[u8]>>());
+    }
+}
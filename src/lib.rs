@@ -31,7 +31,9 @@
 //!     content_transfer_encoding: ContentTransferEncodingHeaderField {
 //!         value: b"8BIT".to_vec()
 //!     },
-//!     content_size: ContentSize(Some(393), Some(9)),
+//!     octets: 393,
+//!     lines: Some(9),
+//!     form: BodyForm::BodyStructure,
 //!     content_md5: ContentMD5HeaderField {
 //!         value: None
 //!     },
@@ -40,8 +42,9 @@
 //!         parameters: Parameters { list: vec![
 //!         ] }
 //!     },
-//!     content_language: ContentLanguageHeaderField { value: None },
+//!     content_language: ContentLanguageHeaderField { value: vec![] },
 //!     content_location: ContentLocationHeaderField { value: None },
+//!     extensions: vec![],
 //!     data: vec![],
 //!     raw_header: vec![],
 //! });
@@ -78,32 +81,55 @@
 //!             content_transfer_encoding: ContentTransferEncodingHeaderField {
 //!                 value: b"base64".to_vec(),
 //!             },
-//!             content_size: ContentSize(Some(1188), Some(16)),
+//!             octets: 1188,
+//!             lines: Some(16),
+//!             form: BodyForm::BodyStructure,
 //!             content_md5: ContentMD5HeaderField { value: None },
 //!             content_disposition: ContentDispositionHeaderField {
 //!                 value: None,
 //!                 parameters: Parameters { list: vec![] },
 //!             },
-//!             content_language: ContentLanguageHeaderField { value: None },
+//!             content_language: ContentLanguageHeaderField { value: vec![] },
 //!             content_location: ContentLocationHeaderField { value: None },
+//!             extensions: vec![],
 //!             data: vec![],
 //!             raw_header: vec![],
 //!         })],
-//!         content_type: b"mixed".to_vec(),
+//!         content_type: MediaType::new(b"multipart", b"mixed"),
+//!         form: BodyForm::BodyStructure,
 //!         parameters: Parameters {
 //!             list: vec![Parameter {
 //!                 attribute: b"boundary".to_vec(),
 //!                 value: b"===============1522363357941492443==".to_vec(),
 //!             }],
 //!         },
+//!         content_disposition: ContentDispositionHeaderField {
+//!             value: None,
+//!             parameters: Parameters { list: vec![] },
+//!         },
+//!         content_language: ContentLanguageHeaderField { value: vec![] },
+//!         content_location: ContentLocationHeaderField { value: None },
+//!         extensions: vec![],
 //!         raw_header: b"Subject: =?utf-8?b?5L2g5aW9IDBiMGZiYjZkYmFmM2FmYmIgenFhLWVtYWls5rWL6K+V?=\r\nFrom: liutianyu@nextcloud.games\r\nTo: shenzongxu@nextcloud.games\r\nDate: Tue, 05 Dec 2023 06:16:58 -0000\r\nMIME-Version: 1.0\r\n".to_vec(),
 //!     }),
 //! );
 //! assert_eq!(r, (b"".as_ref(), h));
 //! ```
 
+pub mod attachment;
+pub mod decode;
+pub mod display;
+pub mod error;
+pub mod media_type;
+pub mod mime;
 pub mod parser;
+pub mod related;
+pub mod borrowed;
+pub mod envelope;
 // Get new SequenceNumbers
 pub mod sequence;
 pub mod extractor;
-pub mod response;
\ No newline at end of file
+pub mod fetch;
+pub mod response;
+pub mod serializer;
+pub mod walk;
\ No newline at end of file
@@ -0,0 +1,141 @@
+use std::hash::{Hash, Hasher};
+
+/// Top-level media type of a part (RFC 2046, RFC 8081).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TopLevelType {
+    Text,
+    Image,
+    Audio,
+    Video,
+    Application,
+    Multipart,
+    Message,
+    Font,
+    Model,
+    Other,
+}
+
+const TOP_LEVEL_TYPES: [(&[u8], TopLevelType); 9] = [
+    (b"text", TopLevelType::Text),
+    (b"image", TopLevelType::Image),
+    (b"audio", TopLevelType::Audio),
+    (b"video", TopLevelType::Video),
+    (b"application", TopLevelType::Application),
+    (b"multipart", TopLevelType::Multipart),
+    (b"message", TopLevelType::Message),
+    (b"font", TopLevelType::Font),
+    (b"model", TopLevelType::Model),
+];
+
+impl From<&[u8]> for TopLevelType {
+    fn from(ttype: &[u8]) -> Self {
+        TOP_LEVEL_TYPES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(ttype))
+            .map_or(TopLevelType::Other, |(_, top_level)| *top_level)
+    }
+}
+
+/// message/rfc822 or message/global, the parts that carry an envelope.
+pub(crate) fn is_encapsulated_message(ttype: &[u8], subtype: &[u8]) -> bool {
+    ttype.eq_ignore_ascii_case(b"message")
+        && (subtype.eq_ignore_ascii_case(b"rfc822") || subtype.eq_ignore_ascii_case(b"global"))
+}
+
+/// A media type and subtype as the server spelled them. Comparison and
+/// hashing ignore ASCII case, output keeps the original spelling.
+#[derive(Debug, Clone)]
+pub struct MediaType {
+    pub ttype: Vec<u8>,
+    pub subtype: Vec<u8>,
+}
+
+impl MediaType {
+    pub fn new(ttype: &[u8], subtype: &[u8]) -> Self {
+        MediaType {
+            ttype: ttype.to_vec(),
+            subtype: subtype.to_vec(),
+        }
+    }
+
+    pub fn top_level(&self) -> TopLevelType {
+        TopLevelType::from(self.ttype.as_slice())
+    }
+
+    /// Case-insensitive match against a type and subtype.
+    pub fn is(&self, ttype: &[u8], subtype: &[u8]) -> bool {
+        self.ttype.eq_ignore_ascii_case(ttype) && self.subtype.eq_ignore_ascii_case(subtype)
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.top_level() == TopLevelType::Text
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.top_level() == TopLevelType::Multipart
+    }
+
+    pub fn is_message(&self) -> bool {
+        self.top_level() == TopLevelType::Message
+    }
+
+    /// message/rfc822 or message/global, the parts that carry an envelope.
+    pub fn is_encapsulated_message(&self) -> bool {
+        is_encapsulated_message(&self.ttype, &self.subtype)
+    }
+
+    /// Lowercase "type/subtype", for lookups and logging.
+    pub fn essence(&self) -> String {
+        String::from_utf8_lossy(&self.get_content_type_text()).to_ascii_lowercase()
+    }
+
+    /// "type/subtype" in the original spelling.
+    pub fn get_content_type_text(&self) -> Vec<u8> {
+        let mut result = self.ttype.to_vec();
+        result.extend_from_slice(b"/");
+        result.extend(self.subtype.to_vec().iter());
+        result
+    }
+}
+
+impl PartialEq for MediaType {
+    fn eq(&self, other: &Self) -> bool {
+        self.is(&other.ttype, &other.subtype)
+    }
+}
+
+impl Eq for MediaType {}
+
+impl Hash for MediaType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for x in self.ttype.iter().chain(b"/").chain(self.subtype.iter()) {
+            state.write_u8(x.to_ascii_lowercase());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_media_type() {
+        let upper = MediaType::new(b"TEXT", b"PLAIN");
+        let lower = MediaType::new(b"text", b"plain");
+        assert_eq!(upper, lower);
+        assert!(upper.is_text());
+        assert!(!upper.is_multipart());
+        assert!(upper.is(b"Text", b"Plain"));
+        assert_eq!(upper.get_content_type_text(), b"TEXT/PLAIN".to_vec());
+        assert_eq!(upper.essence(), "text/plain");
+        let set: HashSet<MediaType> = [upper, lower].into_iter().collect();
+        assert_eq!(set.len(), 1);
+        assert_ne!(MediaType::new(b"text", b"html"), MediaType::new(b"text", b"plain"));
+        assert_eq!(MediaType::new(b"Multipart", b"mixed").top_level(), TopLevelType::Multipart);
+        assert_eq!(MediaType::new(b"x-custom", b"a").top_level(), TopLevelType::Other);
+        assert!(MediaType::new(b"MESSAGE", b"Global").is_encapsulated_message());
+        assert_eq!(TopLevelType::from(b"ApPlIcAtIoN".as_ref()), TopLevelType::Application);
+        assert!(!is_encapsulated_message(b"message", b"partial"));
+    }
+}
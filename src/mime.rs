@@ -0,0 +1,614 @@
+//! Builds the `Body` tree of a raw RFC 5322 message, the way an IMAP server
+//! computes BODYSTRUCTURE.
+//!
+//! Bare LF line endings are read as CRLF, as a server stores the message.
+//! Octets count the part's body after its header, without the CRLF that
+//! belongs to the next boundary; lines count the lines of that body.
+
+use crate::envelope::{Address, AddressEntry, Envelope};
+use crate::parser::{
+    unescape_quoted, Body, BodyForm, ContentDescriptionHeaderField, ContentDispositionHeaderField,
+    ContentIDHeaderField, ContentLanguageHeaderField, ContentLocationHeaderField,
+    ContentMD5HeaderField, ContentTransferEncodingHeaderField, ContentTypeHeaderField, MediaType,
+    MessageBody, MultiBody, Parameter, Parameters, SingleBody,
+};
+
+/// Host RFC 3501 servers report for an address without a domain, since a
+/// NIL host would start a group.
+const MISSING_HOST: &[u8] = b".MISSING-HOST-NAME.";
+
+/// The body of `message`, with each part's `data` and `raw_header` filled.
+/// `raw_header` keeps the fields other than Content-*, which `get_text`
+/// writes from the parsed fields.
+pub fn body_from_message(message: &[u8]) -> Body {
+    build(&to_crlf(message), false, true)
+}
+
+/// The body of `message` as a server reports it in BODYSTRUCTURE, so it
+/// compares equal to what `body_parser` gives for the server's response.
+pub fn bodystructure_from_message(message: &[u8]) -> Body {
+    build(&to_crlf(message), false, false)
+}
+
+/// The ENVELOPE a server reports for `message`.
+pub fn envelope_from_message(message: &[u8]) -> Envelope {
+    let message = to_crlf(message);
+    let (header, _) = split_part(&message);
+    envelope_from_fields(&header_fields(header))
+}
+
+fn to_crlf(s: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(s.len());
+    for (index, x) in s.iter().enumerate() {
+        if *x == b'\n' && (index == 0 || s[index - 1] != b'\r') {
+            result.push(b'\r');
+        }
+        result.push(*x);
+    }
+    result
+}
+
+fn count_lines(s: &[u8]) -> u32 {
+    let lines = s.iter().filter(|x| **x == b'\n').count();
+    let last = usize::from(!s.is_empty() && !s.ends_with(b"\n"));
+    (lines + last) as u32
+}
+
+/// Header (with its last CRLF) and body of a message or part.
+fn split_part(s: &[u8]) -> (&[u8], &[u8]) {
+    if let Some(body) = s.strip_prefix(b"\r\n") {
+        return (b"", body);
+    }
+    match s.windows(4).position(|x| x == b"\r\n\r\n") {
+        Some(index) => (&s[..index + 2], &s[index + 4..]),
+        None => (s, b""),
+    }
+}
+
+struct HeaderField<'a> {
+    name: &'a [u8],
+    /// The value unfolded and trimmed.
+    value: Vec<u8>,
+    /// The whole field as it was written, with its CRLF.
+    raw: &'a [u8],
+}
+
+fn header_fields(header: &[u8]) -> Vec<HeaderField<'_>> {
+    let mut fields = vec![];
+    let mut start = 0;
+    while start < header.len() {
+        let mut end = start;
+        // A field goes on while the next line starts with whitespace.
+        loop {
+            end = match header[end..].windows(2).position(|x| x == b"\r\n") {
+                Some(index) => end + index + 2,
+                None => header.len(),
+            };
+            if end >= header.len() || !matches!(header[end], b' ' | b'\t') {
+                break;
+            }
+        }
+        let raw = &header[start..end];
+        if let Some(colon) = raw.iter().position(|x| *x == b':') {
+            let value: Vec<u8> = raw[colon + 1..]
+                .iter()
+                .copied()
+                .filter(|x| *x != b'\r' && *x != b'\n')
+                .collect();
+            fields.push(HeaderField {
+                name: raw[..colon].trim_ascii(),
+                value: value.trim_ascii().to_vec(),
+                raw,
+            });
+        }
+        start = end;
+    }
+    fields
+}
+
+fn field<'a>(fields: &'a [HeaderField], name: &[u8]) -> Option<&'a [u8]> {
+    fields
+        .iter()
+        .find(|x| x.name.eq_ignore_ascii_case(name))
+        .map(|x| x.value.as_slice())
+}
+
+/// Removes RFC 5322 comments, keeping quoted strings whole.
+fn strip_comments(s: &[u8]) -> Vec<u8> {
+    let mut result = vec![];
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut i = 0;
+    while i < s.len() {
+        let x = s[i];
+        if x == b'\\' && (quoted || depth > 0) {
+            if depth == 0 {
+                result.extend_from_slice(&s[i..(i + 2).min(s.len())]);
+            }
+            i += 2;
+            continue;
+        }
+        if depth == 0 && x == b'"' {
+            quoted = !quoted;
+        } else if !quoted && x == b'(' {
+            depth += 1;
+        } else if !quoted && depth > 0 && x == b')' {
+            depth -= 1;
+            i += 1;
+            continue;
+        }
+        if depth == 0 {
+            result.push(x);
+        }
+        i += 1;
+    }
+    result
+}
+
+/// Index of the first byte of `stops` in `s` from `start` on that is not
+/// inside a quoted string, or the end of `s`.
+fn find_unquoted(s: &[u8], start: usize, stops: &[u8]) -> usize {
+    let mut quoted = false;
+    let mut i = start;
+    while i < s.len() {
+        match s[i] {
+            b'\\' if quoted => i += 1,
+            b'"' => quoted = !quoted,
+            x if !quoted && stops.contains(&x) => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    s.len()
+}
+
+fn unquote(s: &[u8]) -> Vec<u8> {
+    let s = s.trim_ascii();
+    match s.strip_prefix(b"\"").and_then(|x| x.strip_suffix(b"\"")) {
+        Some(inner) => unescape_quoted(inner).into_owned(),
+        None => s.to_vec(),
+    }
+}
+
+/// The value before the first ";" and the parameters after it, as in
+/// Content-Type and Content-Disposition.
+fn value_and_parameters(s: &[u8]) -> (Vec<u8>, Parameters) {
+    let s = strip_comments(s);
+    let mut end = find_unquoted(&s, 0, b";");
+    let value = s[..end].trim_ascii().to_vec();
+    let mut parameters = Parameters::default();
+    while end < s.len() {
+        let start = end + 1;
+        end = find_unquoted(&s, start, b";");
+        let param = &s[start..end];
+        if let Some(equal) = param.iter().position(|x| *x == b'=') {
+            parameters.list.push(Parameter {
+                attribute: param[..equal].trim_ascii().to_vec(),
+                value: unquote(&param[equal + 1..]),
+            });
+        }
+    }
+    (value, parameters)
+}
+
+/// The media type and parameters of a part, with RFC 2045's default when
+/// Content-Type is missing or has no subtype.
+fn content_type(fields: &[HeaderField], in_digest: bool) -> (MediaType, Parameters) {
+    if let Some(value) = field(fields, b"Content-Type") {
+        let (value, parameters) = value_and_parameters(value);
+        if let Some(slash) = value.iter().position(|x| *x == b'/') {
+            let media_type = MediaType::new(value[..slash].trim_ascii(), value[slash + 1..].trim_ascii());
+            return (media_type, parameters);
+        }
+    }
+    if in_digest {
+        return (MediaType::new(b"message", b"rfc822"), Parameters::default());
+    }
+    let mut parameters = Parameters::default();
+    parameters.set(b"charset", b"us-ascii");
+    (MediaType::new(b"text", b"plain"), parameters)
+}
+
+/// Parts of a multipart body, or None if no delimiter line is found.
+fn split_multipart<'a>(body: &'a [u8], boundary: &[u8]) -> Option<Vec<&'a [u8]>> {
+    let mut delimiter = b"--".to_vec();
+    delimiter.extend_from_slice(boundary);
+    // Start of each delimiter line, and whether it closes the multipart.
+    let mut delimiters = vec![];
+    let mut line = 0;
+    while line < body.len() {
+        let next = match body[line..].windows(2).position(|x| x == b"\r\n") {
+            Some(index) => line + index + 2,
+            None => body.len(),
+        };
+        if let Some(rest) = body[line..next].strip_prefix(delimiter.as_slice()) {
+            let close = rest.starts_with(b"--");
+            let rest = rest.strip_prefix(b"--").unwrap_or(rest);
+            if rest.trim_ascii().is_empty() {
+                delimiters.push((line, next, close));
+                if close {
+                    break;
+                }
+            }
+        }
+        line = next;
+    }
+    if delimiters.is_empty() {
+        return None;
+    }
+    let mut parts = vec![];
+    for (index, (_, content_start, close)) in delimiters.iter().enumerate() {
+        if *close {
+            break;
+        }
+        let content_end = match delimiters.get(index + 1) {
+            // The CRLF before a delimiter belongs to the delimiter.
+            Some((next_line, _, _)) => next_line.saturating_sub(2).max(*content_start),
+            None => body.len(),
+        };
+        parts.push(&body[*content_start..content_end]);
+    }
+    Some(parts)
+}
+
+fn parse_address_spec(s: &[u8]) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+    let spec: Vec<u8> = s.iter().copied().filter(|x| !x.is_ascii_whitespace()).collect();
+    match spec.iter().rposition(|x| *x == b'@') {
+        Some(at) => (Some(unquote(&spec[..at])), Some(spec[at + 1..].to_vec())),
+        None => (Some(unquote(&spec)), Some(MISSING_HOST.to_vec())),
+    }
+}
+
+/// A display name with quoted strings unquoted and whitespace collapsed.
+fn phrase(s: &[u8]) -> Option<Vec<u8>> {
+    let mut result = vec![];
+    let mut start = 0;
+    while start < s.len() {
+        if s[start] == b'"' {
+            // The quoted string ends at the next quote that is not escaped.
+            let mut end = start + 1;
+            while end < s.len() && s[end] != b'"' {
+                end += if s[end] == b'\\' { 2 } else { 1 };
+            }
+            let end = end.min(s.len());
+            result.append(&mut unescape_quoted(&s[start + 1..end]).into_owned());
+            start = end + 1;
+        } else if s[start].is_ascii_whitespace() {
+            if !result.ends_with(b" ") {
+                result.push(b' ');
+            }
+            start += 1;
+        } else {
+            result.push(s[start]);
+            start += 1;
+        }
+    }
+    let result = result.trim_ascii();
+    (!result.is_empty()).then(|| result.to_vec())
+}
+
+/// "[name] <[@route:]mailbox@host>" or a bare "mailbox@host".
+fn mailbox(name: &[u8], angle: Option<&[u8]>) -> Option<Address> {
+    let (name, spec) = match angle {
+        Some(spec) => (phrase(name), spec),
+        None => (None, name),
+    };
+    if spec.trim_ascii().is_empty() {
+        return None;
+    }
+    let (adl, spec) = match spec.iter().position(|x| *x == b':') {
+        Some(colon) if spec.trim_ascii().starts_with(b"@") => {
+            (Some(spec[..colon].trim_ascii().to_vec()), &spec[colon + 1..])
+        }
+        _ => (None, spec),
+    };
+    let (mailbox, host) = parse_address_spec(spec);
+    Some(Address {
+        name,
+        adl,
+        mailbox,
+        host,
+    })
+}
+
+/// An RFC 5322 address-list with its groups.
+fn address_list(value: &[u8]) -> Vec<AddressEntry> {
+    let s = strip_comments(value);
+    let mut entries = vec![];
+    let mut group: Option<(Vec<u8>, Vec<Address>)> = None;
+    let mut i = 0;
+    while i < s.len() {
+        let end = find_unquoted(&s, i, b",:;<");
+        let (address, next) = match s.get(end) {
+            Some(b':') if group.is_none() => {
+                group = Some((phrase(&s[i..end]).unwrap_or_default(), vec![]));
+                i = end + 1;
+                continue;
+            }
+            Some(b'<') => {
+                let close = find_unquoted(&s, end + 1, b">");
+                let address = mailbox(&s[i..end], Some(&s[end + 1..close]));
+                // Skip whatever is left up to the next separator.
+                (address, find_unquoted(&s, close, b",;"))
+            }
+            _ => (mailbox(&s[i..end], None), end),
+        };
+        match (&mut group, address) {
+            (Some((_, members)), Some(address)) => members.push(address),
+            (None, Some(address)) => entries.push(AddressEntry::Mailbox(address)),
+            _ => {}
+        }
+        if s.get(next) == Some(&b';') {
+            if let Some((name, members)) = group.take() {
+                entries.push(AddressEntry::Group { name, members });
+            }
+        }
+        i = next + 1;
+    }
+    if let Some((name, members)) = group {
+        entries.push(AddressEntry::Group { name, members });
+    }
+    entries
+}
+
+fn envelope_from_fields(fields: &[HeaderField]) -> Envelope {
+    let addresses = |name: &[u8]| field(fields, name).map(address_list).unwrap_or_default();
+    let from = addresses(b"From");
+    // RFC 3501 defaults Sender and Reply-To to From.
+    let sender = field(fields, b"Sender").map_or_else(|| addresses(b"From"), address_list);
+    let reply_to = field(fields, b"Reply-To").map_or_else(|| addresses(b"From"), address_list);
+    Envelope {
+        date: field(fields, b"Date").map(<[u8]>::to_vec),
+        subject: field(fields, b"Subject").map(<[u8]>::to_vec),
+        from,
+        sender,
+        reply_to,
+        to: addresses(b"To"),
+        cc: addresses(b"Cc"),
+        bcc: addresses(b"Bcc"),
+        in_reply_to: field(fields, b"In-Reply-To").map(<[u8]>::to_vec),
+        message_id: field(fields, b"Message-ID").map(<[u8]>::to_vec),
+    }
+}
+
+fn raw_header(fields: &[HeaderField]) -> Vec<u8> {
+    fields
+        .iter()
+        .filter(|x| x.name.len() < 8 || !x.name[..8].eq_ignore_ascii_case(b"Content-"))
+        .flat_map(|x| x.raw.iter().copied())
+        .collect()
+}
+
+fn optional(fields: &[HeaderField], name: &[u8]) -> Option<Vec<u8>> {
+    field(fields, name).map(<[u8]>::to_vec)
+}
+
+fn disposition(fields: &[HeaderField]) -> ContentDispositionHeaderField {
+    match field(fields, b"Content-Disposition") {
+        Some(value) => {
+            let (value, parameters) = value_and_parameters(value);
+            ContentDispositionHeaderField {
+                value: Some(value),
+                parameters,
+            }
+        }
+        None => ContentDispositionHeaderField {
+            value: None,
+            parameters: Parameters::default(),
+        },
+    }
+}
+
+fn language(fields: &[HeaderField]) -> ContentLanguageHeaderField {
+    let value = field(fields, b"Content-Language")
+        .map(|x| {
+            strip_comments(x)
+                .split(|x| *x == b',')
+                .map(|x| x.trim_ascii().to_vec())
+                .filter(|x| !x.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    ContentLanguageHeaderField { value }
+}
+
+/// The body of the message or part `s`. Parts of a multipart/digest
+/// default to message/rfc822.
+fn build(s: &[u8], in_digest: bool, fill: bool) -> Body {
+    let (header, body) = split_part(s);
+    let fields = header_fields(header);
+    let (mut media_type, mut parameters) = content_type(&fields, in_digest);
+    let raw_header = if fill { self::raw_header(&fields) } else { vec![] };
+    if media_type.is_multipart() {
+        let parts = parameters
+            .get(b"boundary")
+            .and_then(|boundary| split_multipart(body, &boundary));
+        if let Some(parts) = parts {
+            let in_digest = media_type.subtype.eq_ignore_ascii_case(b"digest");
+            return Body::Multi(MultiBody {
+                parts: parts.into_iter().map(|x| build(x, in_digest, fill)).collect(),
+                content_type: media_type,
+                form: BodyForm::BodyStructure,
+                parameters,
+                content_disposition: disposition(&fields),
+                content_language: language(&fields),
+                content_location: ContentLocationHeaderField {
+                    value: optional(&fields, b"Content-Location"),
+                },
+                extensions: vec![],
+                raw_header,
+            });
+        }
+        // A multipart needs a boundary that splits its body (RFC 2046 5.1);
+        // without one it has no parts and is taken as opaque data.
+        media_type = MediaType::new(b"application", b"octet-stream");
+        parameters = Parameters::default();
+    }
+    let content_type = ContentTypeHeaderField {
+        ttype: media_type,
+        parameters,
+    };
+    let content_id = ContentIDHeaderField {
+        value: optional(&fields, b"Content-ID"),
+    };
+    let content_description = ContentDescriptionHeaderField {
+        value: optional(&fields, b"Content-Description"),
+    };
+    let content_transfer_encoding = ContentTransferEncodingHeaderField {
+        value: optional(&fields, b"Content-Transfer-Encoding").unwrap_or(b"7BIT".to_vec()),
+    };
+    let content_md5 = ContentMD5HeaderField {
+        value: optional(&fields, b"Content-MD5"),
+    };
+    let content_location = ContentLocationHeaderField {
+        value: optional(&fields, b"Content-Location"),
+    };
+    let data = if fill { body.to_vec() } else { vec![] };
+    if content_type.ttype.is_encapsulated_message() {
+        let (inner_header, _) = split_part(body);
+        return Body::Message(MessageBody {
+            content_type,
+            content_id,
+            content_description,
+            content_transfer_encoding,
+            octets: body.len(),
+            envelope: envelope_from_fields(&header_fields(inner_header)),
+            body: Box::new(build(body, false, fill)),
+            lines: count_lines(body),
+            form: BodyForm::BodyStructure,
+            content_md5,
+            content_disposition: disposition(&fields),
+            content_language: language(&fields),
+            content_location,
+            extensions: vec![],
+            data,
+            raw_header,
+        });
+    }
+    let lines = content_type.ttype.is_text().then(|| count_lines(body));
+    Body::Single(SingleBody {
+        content_type,
+        content_id,
+        content_description,
+        content_transfer_encoding,
+        octets: body.len(),
+        lines,
+        form: BodyForm::BodyStructure,
+        content_md5,
+        content_disposition: disposition(&fields),
+        content_language: language(&fields),
+        content_location,
+        extensions: vec![],
+        data,
+        raw_header,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::body_parser;
+
+    const MESSAGE: &[u8] = b"From: \"Alice A.\" <alice@example.com>\r\n\
+To: team: bob@example.org, Carol <carol@example.net>;, dave@example.com\r\n\
+Subject: Test\r\n\
+\x20folded\r\n\
+Date: Tue, 05 Dec 2023 06:16:58 +0000\r\n\
+Message-ID: <1@example.com>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b1\" (comment)\r\n\
+\r\n\
+preamble\r\n\
+--b1\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+\r\n\
+Hello\r\n\
+world\r\n\
+--b1\r\n\
+Content-Type: application/pdf; name=\"a.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+Content-Disposition: attachment;\r\n\
+\tfilename=\"a.pdf\"\r\n\
+Content-Language: en, de\r\n\
+\r\n\
+QUJD\r\n\
+--b1\r\n\
+Content-Type: message/rfc822\r\n\
+\r\n\
+Subject: Inner\r\n\
+From: eve\r\n\
+\r\n\
+Inner body\r\n\
+--b1--\r\n\
+epilogue\r\n";
+
+    #[test]
+    fn test_bodystructure_from_message() {
+        let expected = body_parser(br#"(("text" "plain" ("charset" "utf-8") NIL NIL "7BIT" 12 2 NIL NIL NIL NIL)("application" "pdf" ("name" "a.pdf") NIL NIL "base64" 4 NIL ("attachment" ("filename" "a.pdf")) ("en" "de") NIL)("message" "rfc822" NIL NIL NIL "7BIT" 39 (NIL "Inner" ((NIL NIL "eve" ".MISSING-HOST-NAME.")) ((NIL NIL "eve" ".MISSING-HOST-NAME.")) ((NIL NIL "eve" ".MISSING-HOST-NAME.")) NIL NIL NIL NIL NIL) ("text" "plain" ("charset" "us-ascii") NIL NIL "7BIT" 10 1 NIL NIL NIL NIL) 4 NIL NIL NIL NIL) "mixed" ("boundary" "b1") NIL NIL NIL)"#)
+            .unwrap()
+            .1;
+        assert_eq!(bodystructure_from_message(MESSAGE), expected);
+        // Bare LF endings give the same structure.
+        let lf: Vec<u8> = MESSAGE.iter().copied().filter(|x| *x != b'\r').collect();
+        assert_eq!(bodystructure_from_message(&lf), expected);
+    }
+
+    #[test]
+    fn test_multipart_without_parts() {
+        let expected = body_parser(br#"("application" "octet-stream" NIL NIL NIL "7BIT" 6 NIL NIL NIL NIL)"#).unwrap().1;
+        let message = b"Content-Type: multipart/mixed\r\n\r\n--b1\r\n";
+        assert_eq!(bodystructure_from_message(message), expected);
+        let message = b"Content-Type: multipart/mixed; boundary=b2\r\n\r\n--b1\r\n";
+        assert_eq!(bodystructure_from_message(message), expected);
+    }
+
+    #[test]
+    fn test_body_from_message() {
+        let body = body_from_message(MESSAGE);
+        let Body::Multi(multi) = &body else {
+            panic!("expected a multipart body");
+        };
+        assert!(multi.raw_header.starts_with(b"From: \"Alice A.\" <alice@example.com>\r\n"));
+        assert!(multi.raw_header.ends_with(b"MIME-Version: 1.0\r\n"));
+        let Body::Single(text) = &multi.parts[0] else {
+            panic!("expected a single part");
+        };
+        assert_eq!(text.data, b"Hello\r\nworld".to_vec());
+        assert!(body.are_all_bodies_with_data());
+    }
+
+    #[test]
+    fn test_envelope_from_message() {
+        let envelope = envelope_from_message(MESSAGE);
+        assert_eq!(envelope.subject, Some(b"Test folded".to_vec()));
+        assert_eq!(envelope.from, envelope.sender);
+        assert_eq!(
+            envelope.from,
+            vec![AddressEntry::Mailbox(Address {
+                name: Some(b"Alice A.".to_vec()),
+                adl: None,
+                mailbox: Some(b"alice".to_vec()),
+                host: Some(b"example.com".to_vec()),
+            })]
+        );
+        assert_eq!(envelope.to.len(), 2);
+        let AddressEntry::Group { name, members } = &envelope.to[0] else {
+            panic!("expected a group");
+        };
+        assert_eq!(name, b"team");
+        assert_eq!(members[1].name, Some(b"Carol".to_vec()));
+        assert!(matches!(&envelope.to[1], AddressEntry::Mailbox(x) if x.mailbox == Some(b"dave".to_vec())));
+        assert_eq!(envelope.message_id, Some(b"<1@example.com>".to_vec()));
+    }
+
+    #[test]
+    fn test_split_multipart() {
+        assert_eq!(
+            split_multipart(b"--a\r\none\r\n--a \r\n\r\n--a--", b"a"),
+            Some(vec![b"one".as_ref(), b"".as_ref()])
+        );
+        assert_eq!(split_multipart(b"--ab\r\nx\r\n", b"a"), None);
+        assert_eq!(count_lines(b"a\r\nb"), 2);
+        assert_eq!(count_lines(b""), 0);
+    }
+}
@@ -1,14 +1,23 @@
-use std::str::from_utf8;
+use std::{borrow::Cow, cell::{Cell, RefCell}, str::from_utf8};
 pub use nom::IResult;
 use nom::{
     branch::alt,
-    bytes::complete::{tag, tag_no_case, take_till},
+    bytes::complete::{tag, tag_no_case, take, take_while1},
     character::complete::digit1,
-    combinator::{map, opt},
-    multi::{many1, separated_list1},
-    sequence::{delimited, tuple},
+    combinator::{map, map_res, opt, verify},
+    multi::{many0, many1, separated_list1},
+    error::ErrorKind,
+    sequence::{delimited, preceded, terminated, tuple},
+    Err as NomErr,
 };
+use crate::envelope::{envelope_ctx, Envelope};
+use crate::borrowed;
+use crate::decode::{self, DecodedParameter};
+use crate::error::{self, Construct, Error};
+use crate::media_type;
+pub use crate::media_type::{MediaType, TopLevelType};
 use crate::sequence;
+use crate::serializer;
 
 /// ```rust
 /// # use imap_bodystructure::parser::head_bodystructure;
@@ -18,17 +27,302 @@ use crate::sequence;
 /// ```
 pub fn head_bodystructure(s: &[u8]) -> IResult<&[u8], &[u8]> {
     map(
-        tuple((tag_no_case(b"BODYSTRUCTURE"), many1(tag(b" ")))),
+        tuple((
+            alt((tag_no_case(b"BODYSTRUCTURE"), tag_no_case(b"BODY"))),
+            many1(tag(b" ")),
+        )),
         |(a, _)| a,
     )(s)
 }
 
-fn is_double_quote(s: u8) -> bool {
-    s == 0x22
+/// Recognizes the content of a quoted string, keeping the backslash escapes
+/// of quoted-specials (`\"` and `\\`) as they appear in the input.
+pub fn double_quoted_string(s: &[u8]) -> IResult<&[u8], &[u8]> {
+    delimited(tag(b"\""), quoted_content, tag(b"\""))(s)
 }
 
-pub fn double_quoted_string(s: &[u8]) -> IResult<&[u8], &[u8]> {
-    delimited(tag(b"\""), take_till(is_double_quote), tag(b"\""))(s)
+fn quoted_content(s: &[u8]) -> IResult<&[u8], &[u8]> {
+    let mut index = 0;
+    while index < s.len() {
+        match s[index] {
+            b'"' => break,
+            b'\\' => match s.get(index + 1) {
+                Some(b'"') | Some(b'\\') => index += 2,
+                _ => {
+                    return Err(NomErr::Error(nom::error::Error {
+                        input: &s[index..],
+                        code: ErrorKind::Escaped,
+                    }))
+                }
+            },
+            _ => index += 1,
+        }
+    }
+    Ok((&s[index..], &s[..index]))
+}
+
+/// Removes the backslash escapes of a quoted string's content.
+pub fn unescape_quoted(s: &[u8]) -> Cow<'_, [u8]> {
+    if !s.contains(&b'\\') {
+        return Cow::Borrowed(s);
+    }
+    let mut result = Vec::with_capacity(s.len());
+    let mut escaped = false;
+    for &i in s {
+        if i == b'\\' && !escaped {
+            escaped = true;
+        } else {
+            result.push(i);
+            escaped = false;
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// The inverse of `unescape_quoted`, used when a value is written back between double quotes.
+pub fn escape_quoted(s: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(s.len());
+    for &i in s {
+        if i == b'"' || i == b'\\' {
+            result.push(b'\\');
+        }
+        result.push(i);
+    }
+    result
+}
+
+/// quoted = DQUOTE *QUOTED-CHAR DQUOTE, returned without its escapes
+pub fn quoted(s: &[u8]) -> IResult<&[u8], Cow<'_, [u8]>> {
+    map(double_quoted_string, unescape_quoted)(s)
+}
+
+fn number_parser(s: &[u8]) -> IResult<&[u8], usize> {
+    map_res(digit1, |x| str::parse::<usize>(from_utf8(x).unwrap()))(s)
+}
+
+/// literal = "{" number ["+"] "}" CRLF *CHAR8, literal8 = "~{" number "}" CRLF *OCTET
+pub fn literal(s: &[u8]) -> IResult<&[u8], &[u8]> {
+    let (s, _) = opt(tag(b"~"))(s)?;
+    let (s, length) = delimited(
+        tag(b"{"),
+        number_parser,
+        tuple((opt(tag(b"+")), tag(b"}"), tag(b"\r\n"))),
+    )(s)?;
+    take(length)(s)
+}
+
+/// string = quoted / literal
+pub fn string(s: &[u8]) -> IResult<&[u8], Cow<'_, [u8]>> {
+    alt((quoted, map(literal, Cow::Borrowed)))(s)
+}
+
+/// nstring = string / nil
+pub fn nstring(s: &[u8]) -> IResult<&[u8], Option<Cow<'_, [u8]>>> {
+    nstring_ctx(&Context::strict(s), s)
+}
+
+/// Options for `body_parser_with_options`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ParserOptions {
+    /// Accept the deviations listed in `WarningKind` instead of failing,
+    /// and record a `ParseWarning` for each one that was repaired.
+    pub lenient: bool,
+    /// The FETCH item the body came from, e.g. `BodyForm::from_keyword` of
+    /// what `head_bodystructure` returns. When None, the body is taken as
+    /// BODYSTRUCTURE if any of its parts carries extension data.
+    pub form: Option<BodyForm>,
+}
+
+/// A deviation from RFC 3501 repaired by the lenient parser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WarningKind {
+    /// More than one space where a single SP belongs.
+    ExtraWhitespace,
+    /// An atom where a quoted string or a literal belongs.
+    UnquotedAtom,
+    /// NIL as body-fld-enc, read as the RFC 2045 default 7BIT.
+    NilTransferEncoding,
+    /// NIL as a parameter value, read as an empty value.
+    NilParameterValue,
+    /// NIL as body-fld-octets, read as 0.
+    NilOctets,
+    /// A multipart body without any body part.
+    EmptyMultipart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseWarning {
+    pub kind: WarningKind,
+    /// Byte offset of the repaired construct in the parsed input.
+    pub offset: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParsedBody {
+    pub body: Body,
+    pub warnings: Vec<ParseWarning>,
+}
+
+/// State shared by the parsers of one `body_parser_with_options` call.
+pub(crate) struct Context {
+    options: ParserOptions,
+    input_len: usize,
+    warnings: RefCell<Vec<ParseWarning>>,
+    /// Part numbers down to the part being parsed. `None` stands for the body
+    /// of a message/rfc822 part, which is numbered 1 unless it is a multipart.
+    path: RefCell<Vec<Option<usize>>>,
+    /// Whether the outermost body being parsed is not a multipart, which
+    /// makes it part 1.
+    single_root: Cell<bool>,
+    /// The failure that got furthest into the input: construct, section, offset.
+    failure: RefCell<Option<(Construct, Vec<usize>, usize)>>,
+}
+
+impl Context {
+    pub(crate) fn new(options: ParserOptions, input: &[u8]) -> Context {
+        Context {
+            options,
+            input_len: input.len(),
+            warnings: RefCell::new(vec![]),
+            path: RefCell::new(vec![]),
+            single_root: Cell::new(false),
+            failure: RefCell::new(None),
+        }
+    }
+
+    pub(crate) fn strict(input: &[u8]) -> Context {
+        Context::new(ParserOptions::default(), input)
+    }
+
+    fn lenient(&self) -> bool {
+        self.options.lenient
+    }
+
+    fn warn(&self, kind: WarningKind, at: &[u8]) {
+        self.warnings.borrow_mut().push(ParseWarning {
+            kind,
+            offset: self.input_len - at.len(),
+        });
+    }
+
+    pub(crate) fn into_warnings(self) -> Vec<ParseWarning> {
+        self.warnings.into_inner()
+    }
+
+    fn section(&self) -> Vec<usize> {
+        let root = self.single_root.get().then_some(1);
+        root.into_iter()
+            .chain(self.path.borrow().iter().map(|x| x.unwrap_or(1)))
+            .collect()
+    }
+
+    /// Runs `parser` for `construct`, remembering where it failed.
+    pub(crate) fn field<'a, O>(
+        &self,
+        construct: Construct,
+        s: &'a [u8],
+        mut parser: impl FnMut(&'a [u8]) -> IResult<&'a [u8], O>,
+    ) -> IResult<&'a [u8], O> {
+        let result = parser(s);
+        if let Err(NomErr::Error(e) | NomErr::Failure(e)) = &result {
+            let offset = self.input_len - e.input.len();
+            let mut failure = self.failure.borrow_mut();
+            if failure.as_ref().is_none_or(|(_, _, furthest)| offset > *furthest) {
+                *failure = Some((construct, self.section(), offset));
+            }
+        }
+        result
+    }
+
+    /// The form of the outermost body: the one in the options, else
+    /// BODYSTRUCTURE if any part carries extension data. Parts only record
+    /// the latter while they are parsed and are all given this form after.
+    fn form(&self, has_extension_data: bool) -> BodyForm {
+        self.options.form.unwrap_or(if has_extension_data {
+            BodyForm::BodyStructure
+        } else {
+            BodyForm::Body
+        })
+    }
+
+    fn is_outermost(&self) -> bool {
+        self.path.borrow().is_empty()
+    }
+
+    pub(crate) fn into_error(self, input: &[u8]) -> Error {
+        let (construct, section, offset) =
+            self.failure.into_inner().unwrap_or((Construct::Body, vec![], 0));
+        Error::Body {
+            construct,
+            section,
+            offset,
+            snippet: error::snippet(input, offset),
+        }
+    }
+}
+
+/// Runs `parser` and drops the warnings it recorded if it fails, so that
+/// alternatives which are tried and abandoned leave no trace.
+fn backtrack<'a, O>(
+    ctx: &Context,
+    s: &'a [u8],
+    mut parser: impl FnMut(&'a [u8]) -> IResult<&'a [u8], O>,
+) -> IResult<&'a [u8], O> {
+    let recorded = ctx.warnings.borrow().len();
+    let result = parser(s);
+    if result.is_err() {
+        ctx.warnings.borrow_mut().truncate(recorded);
+    }
+    result
+}
+
+/// SP, or any run of spaces in lenient mode.
+pub(crate) fn sp<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
+    if ctx.lenient() {
+        let (remain, spaces) = take_while1(|x| x == b' ')(s)?;
+        if spaces.len() > 1 {
+            ctx.warn(WarningKind::ExtraWhitespace, s);
+        }
+        Ok((remain, spaces))
+    } else {
+        tag(b" ")(s)
+    }
+}
+
+fn is_atom_char(s: u8) -> bool {
+    s > 0x20 && s < 0x7f && !b"(){%*\"\\]".contains(&s)
+}
+
+/// string, or an atom in lenient mode.
+pub(crate) fn string_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], Cow<'a, [u8]>> {
+    match string(s) {
+        Err(NomErr::Error(_)) if ctx.lenient() => {
+            let (remain, atom) = take_while1(is_atom_char)(s)?;
+            ctx.warn(WarningKind::UnquotedAtom, s);
+            Ok((remain, Cow::Borrowed(atom)))
+        }
+        result => result,
+    }
+}
+
+/// NIL where RFC 3501 has no NIL, accepted in lenient mode only.
+fn lenient_nil<'a>(ctx: &Context, kind: WarningKind, s: &'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
+    let (remain, nil) = tag_no_case(b"NIL")(s)?;
+    if !ctx.lenient() {
+        return Err(NomErr::Error(nom::error::Error::new(s, ErrorKind::Tag)));
+    }
+    ctx.warn(kind, s);
+    Ok((remain, nil))
+}
+
+pub(crate) fn nstring_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], Option<Cow<'a, [u8]>>> {
+    alt((
+        map(tag_no_case(b"NIL"), |_| None),
+        map(|i| string_ctx(ctx, i), Some),
+    ))(s)
 }
 
 #[derive(Debug, PartialEq)]
@@ -41,59 +335,205 @@ impl Parameter {
     pub fn get_content_type_text(&self) -> Vec<u8> {
         let mut result = self.attribute.clone();
         result.extend_from_slice(b"=\"");
-        result.extend(self.value.clone().iter());
+        result.append(&mut escape_quoted(&self.value));
         result.extend_from_slice(b"\"");
         result
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Default)]
 pub struct Parameters {
     pub list: Vec<Parameter>,
 }
 
+/// Section number of `attribute` when it is an RFC 2231 continuation of
+/// `name`, i.e. "name*N" or "name*N*"; a lone "name*" counts as section 0.
+fn continuation_section(attribute: &[u8], name: &[u8]) -> Option<usize> {
+    if attribute.len() <= name.len() || !attribute[..name.len()].eq_ignore_ascii_case(name) {
+        return None;
+    }
+    let rest = attribute[name.len()..].strip_prefix(b"*")?;
+    let digits = rest.strip_suffix(b"*").unwrap_or(rest);
+    if digits.is_empty() {
+        return if rest.is_empty() { Some(0) } else { None };
+    }
+    from_utf8(digits).ok()?.parse().ok()
+}
+
+impl Parameters {
+    /// Value of the parameter `name`, ignoring ASCII case. A parameter split
+    /// into RFC 2231 continuations comes back as one value, still encoded.
+    pub fn get(&self, name: &[u8]) -> Option<Cow<'_, [u8]>> {
+        if let Some(param) = self.list.iter().find(|x| x.attribute.eq_ignore_ascii_case(name)) {
+            return Some(Cow::Borrowed(&param.value));
+        }
+        let mut sections: Vec<(usize, &[u8])> = self
+            .list
+            .iter()
+            .filter_map(|x| continuation_section(&x.attribute, name).map(|n| (n, x.value.as_slice())))
+            .collect();
+        sections.sort_by_key(|(n, _)| *n);
+        match sections.as_slice() {
+            [] => None,
+            [(_, value)] => Some(Cow::Borrowed(value)),
+            _ => Some(Cow::Owned(sections.iter().flat_map(|(_, value)| value.iter().copied()).collect())),
+        }
+    }
+
+    /// Value of the parameter `name` decoded per RFC 2231, or per RFC 2047 when
+    /// only a plain form exists. The extended or continued form wins over a
+    /// plain one, as RFC 2231 readers prefer it.
+    pub fn get_decoded(&self, name: &[u8]) -> Option<DecodedParameter> {
+        let mut sections: Vec<(usize, &[u8], bool)> = self
+            .list
+            .iter()
+            .filter_map(|x| {
+                continuation_section(&x.attribute, name)
+                    .map(|n| (n, x.value.as_slice(), x.attribute.ends_with(b"*")))
+            })
+            .collect();
+        if sections.is_empty() {
+            // Many mailers put RFC 2047 encoded words in plain parameters.
+            let param = self.list.iter().find(|x| x.attribute.eq_ignore_ascii_case(name))?;
+            return Some(DecodedParameter {
+                value: decode::decode_encoded_words(&param.value),
+                charset: None,
+                language: None,
+            });
+        }
+        sections.sort_by_key(|(n, _, _)| *n);
+        let sections: Vec<(&[u8], bool)> = sections.into_iter().map(|(_, value, extended)| (value, extended)).collect();
+        Some(decode::decode_rfc2231(&sections))
+    }
+
+    /// Values of every parameter spelled `name`, for servers that repeat one.
+    pub fn get_all(&self, name: &[u8]) -> Vec<&[u8]> {
+        self.list
+            .iter()
+            .filter(|x| x.attribute.eq_ignore_ascii_case(name))
+            .map(|x| x.value.as_slice())
+            .collect()
+    }
+
+    /// Replaces `name` and any continuations of it with a single parameter,
+    /// kept where the first of them was.
+    pub fn set(&mut self, name: &[u8], value: &[u8]) {
+        let position = self
+            .list
+            .iter()
+            .position(|x| is_named(x, name))
+            .unwrap_or(self.list.len());
+        self.list.retain(|x| !is_named(x, name));
+        self.list.insert(
+            position,
+            Parameter {
+                attribute: name.to_vec(),
+                value: value.to_vec(),
+            },
+        );
+    }
+
+    /// Removes `name` and any continuations of it, returning what `get` would have.
+    pub fn remove(&mut self, name: &[u8]) -> Option<Vec<u8>> {
+        let value = self.get(name).map(Cow::into_owned);
+        self.list.retain(|x| !is_named(x, name));
+        value
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Parameter> {
+        self.list.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
+
+fn is_named(param: &Parameter, name: &[u8]) -> bool {
+    param.attribute.eq_ignore_ascii_case(name) || continuation_section(&param.attribute, name).is_some()
+}
+
+impl<'a> IntoIterator for &'a Parameters {
+    type Item = &'a Parameter;
+    type IntoIter = std::slice::Iter<'a, Parameter>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.iter()
+    }
+}
+
 pub fn parameter(s: &[u8]) -> IResult<&[u8], Parameter> {
-    map(
-        tuple((double_quoted_string, tag(b" "), double_quoted_string)),
-        |(attribute, _, value)| Parameter {
-            attribute: attribute.to_vec(),
-            value: value.to_vec(),
-        },
-    )(s)
+    let (remain, parameter) = parameter_ctx(&Context::strict(s), s)?;
+    Ok((remain, parameter.into_owned()))
 }
 
-pub fn parameters(s: &[u8]) -> IResult<&[u8], Parameters> {
+fn parameter_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], borrowed::Parameter<'a>> {
     map(
-        alt((
-            map(tag_no_case("NIL"), |_| vec![]),
-            delimited(tag(b"("), separated_list1(tag(b" "), parameter), tag(b")")),
+        tuple((
+            |i| string_ctx(ctx, i),
+            |i| sp(ctx, i),
+            alt((
+                map(|i| lenient_nil(ctx, WarningKind::NilParameterValue, i), |_| {
+                    Cow::Borrowed(b"".as_ref())
+                }),
+                |i| string_ctx(ctx, i),
+            )),
         )),
-        |list| Parameters { list: list },
+        |(attribute, _, value)| borrowed::Parameter { attribute, value },
     )(s)
 }
 
-#[derive(Debug, PartialEq)]
-pub struct ContentTypeTypeAndSubType {
-    pub ttype: Vec<u8>,
-    pub subtype: Vec<u8>,
+pub fn parameters(s: &[u8]) -> IResult<&[u8], Parameters> {
+    let (remain, list) = parameters_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
+        Parameters {
+            list: list.into_iter().map(borrowed::Parameter::into_owned).collect(),
+        },
+    ))
 }
 
-impl ContentTypeTypeAndSubType {
-    pub fn get_content_type_text(&self) -> Vec<u8> {
-        let mut result = self.ttype.to_vec();
-        result.extend_from_slice(b"/");
-        result.extend(self.subtype.to_vec().iter());
-        result
-    }
+fn parameters_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], Vec<borrowed::Parameter<'a>>> {
+    alt((
+        map(tag_no_case("NIL"), |_| vec![]),
+        delimited(
+            tag(b"("),
+            separated_list1(|i| sp(ctx, i), |i| parameter_ctx(ctx, i)),
+            tag(b")"),
+        ),
+    ))(s)
 }
 
+/// The media type of a part; kept under its old name.
+pub type ContentTypeTypeAndSubType = MediaType;
+
 pub fn content_type_main(s: &[u8]) -> IResult<&[u8], ContentTypeTypeAndSubType> {
-    map(
-        tuple((double_quoted_string, tag(b" "), double_quoted_string)),
-        |(ttype, _, subtype)| ContentTypeTypeAndSubType {
-            ttype: ttype.to_vec(),
-            subtype: subtype.to_vec(),
+    let (remain, (ttype, subtype)) = content_type_main_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
+        ContentTypeTypeAndSubType {
+            ttype: ttype.into_owned(),
+            subtype: subtype.into_owned(),
         },
+    ))
+}
+
+type TypeAndSubType<'a> = (Cow<'a, [u8]>, Cow<'a, [u8]>);
+
+fn content_type_main_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], TypeAndSubType<'a>> {
+    map(
+        tuple((|i| string_ctx(ctx, i), |i| sp(ctx, i), |i| string_ctx(ctx, i))),
+        |(ttype, _, subtype)| (ttype, subtype),
     )(s)
 }
 
@@ -107,7 +547,7 @@ impl ContentTypeHeaderField {
     pub fn get_text(&self) -> Vec<u8> {
         let mut result = b"Content-Type: ".to_vec();
         result.append(&mut self.ttype.get_content_type_text());
-        for param in &self.parameters.list {
+        for param in &self.parameters {
             // result.extend_from_slice(b"; ");
             result.extend_from_slice(b";\r\n");
             result.extend_from_slice(b"        ");
@@ -119,14 +559,38 @@ impl ContentTypeHeaderField {
 }
 
 pub fn content_type_header_field_parser(s: &[u8]) -> IResult<&[u8], ContentTypeHeaderField> {
-    map(
-        tuple((content_type_main, tag(b" "), parameters)),
-        // Initialism: cttast for ContentTypeTypeAndSubType
-        |(cttast, _, params)| ContentTypeHeaderField {
-            ttype: cttast,
-            parameters: params,
+    let (remain, (ttype, subtype, parameters)) =
+        content_type_header_field_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
+        ContentTypeHeaderField {
+            ttype: ContentTypeTypeAndSubType {
+                ttype: ttype.into_owned(),
+                subtype: subtype.into_owned(),
+            },
+            parameters: Parameters {
+                list: parameters.into_iter().map(borrowed::Parameter::into_owned).collect(),
+            },
         },
-    )(s)
+    ))
+}
+
+/// media type, media subtype and body-fld-param
+type ContentType<'a> = (Cow<'a, [u8]>, Cow<'a, [u8]>, Vec<borrowed::Parameter<'a>>);
+
+fn content_type_header_field_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], ContentType<'a>> {
+    ctx.field(
+        Construct::ContentType,
+        s,
+        map(
+            tuple((
+                |i| content_type_main_ctx(ctx, i),
+                |i| sp(ctx, i),
+                |i| parameters_ctx(ctx, i),
+            )),
+            |((ttype, subtype), _, params)| (ttype, subtype, params),
+        ),
+    )
 }
 /// RFC 2046
 #[derive(Debug, PartialEq)]
@@ -147,15 +611,17 @@ impl ContentIDHeaderField {
 }
 
 pub fn content_id_header_field_parser(s: &[u8]) -> IResult<&[u8], ContentIDHeaderField> {
-    map(alt((tag_no_case(b"NIL"), double_quoted_string)), |val| {
-        if val.to_ascii_lowercase() == b"nil" {
-            ContentIDHeaderField { value: None }
-        } else {
-            ContentIDHeaderField {
-                value: Some(val.to_vec()),
-            }
-        }
-    })(s)
+    let (remain, value) = content_id_header_field_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
+        ContentIDHeaderField {
+            value: value.map(Cow::into_owned),
+        },
+    ))
+}
+
+fn content_id_header_field_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], Option<Cow<'a, [u8]>>> {
+    ctx.field(Construct::ContentId, s, |i| nstring_ctx(ctx, i))
 }
 
 /// RFC 2047
@@ -165,6 +631,11 @@ pub struct ContentDescriptionHeaderField {
 }
 
 impl ContentDescriptionHeaderField {
+    /// The description with RFC 2047 encoded words decoded.
+    pub fn get_decoded(&self) -> Option<String> {
+        self.value.as_deref().map(decode::decode_encoded_words)
+    }
+
     pub fn get_text(&self) -> Option<Vec<u8>> {
         if let Some(value) = self.value.clone() {
             let mut result = b"Content-Description: ".to_vec();
@@ -176,18 +647,18 @@ impl ContentDescriptionHeaderField {
     }
 }
 
-pub fn content_description_header_field_parser(
-    s: &[u8],
-) -> IResult<&[u8], ContentDescriptionHeaderField> {
-    map(alt((tag_no_case(b"NIL"), double_quoted_string)), |val| {
-        if val.to_ascii_lowercase() == b"nil" {
-            ContentDescriptionHeaderField { value: None }
-        } else {
-            ContentDescriptionHeaderField {
-                value: Some(val.to_vec()),
-            }
-        }
-    })(s)
+pub fn content_description_header_field_parser(s: &[u8]) -> IResult<&[u8], ContentDescriptionHeaderField> {
+    let (remain, value) = content_description_header_field_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
+        ContentDescriptionHeaderField {
+            value: value.map(Cow::into_owned),
+        },
+    ))
+}
+
+fn content_description_header_field_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], Option<Cow<'a, [u8]>>> {
+    ctx.field(Construct::ContentDescription, s, |i| nstring_ctx(ctx, i))
 }
 #[derive(Debug, PartialEq)]
 pub struct ContentTransferEncodingHeaderField {
@@ -206,64 +677,50 @@ impl ContentTransferEncodingHeaderField {
 pub fn content_transfer_encoding_header_field_parser(
     s: &[u8],
 ) -> IResult<&[u8], ContentTransferEncodingHeaderField> {
-    map(double_quoted_string, |val| {
+    let (remain, value) = content_transfer_encoding_header_field_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
         ContentTransferEncodingHeaderField {
-            value: val.to_vec(),
-        }
-    })(s)
+            value: value.into_owned(),
+        },
+    ))
 }
 
-#[derive(Debug, PartialEq)]
-pub struct ContentSize(pub Option<usize>, pub Option<usize>);
+fn content_transfer_encoding_header_field_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], Cow<'a, [u8]>> {
+    ctx.field(
+        Construct::ContentTransferEncoding,
+        s,
+        alt((
+            map(|i| lenient_nil(ctx, WarningKind::NilTransferEncoding, i), |_| {
+                Cow::Borrowed(b"7BIT".as_ref())
+            }),
+            |i| string_ctx(ctx, i),
+        )),
+    )
+}
 
-impl ContentSize {
-    pub fn get_text(&self) -> Vec<u8> {
-        if let Some(value) = self.0 {
-            let tmp_string = value.to_string();
-            let mut result = tmp_string.as_str().as_bytes().to_vec();
-            if let Some(value2) = self.1 {
-                let value2 = value2.to_string();
-                result.append(&mut vec![0x20]);
-                result.append(&mut value2.as_str().as_bytes().to_vec());
-            }
-            result
-        } else {
-            vec![0x30]
-        }
-    }
+/// body-fld-octets = number
+pub fn octets_parser(s: &[u8]) -> IResult<&[u8], usize> {
+    octets_ctx(&Context::strict(s), s)
 }
 
-pub fn content_size_parser(s: &[u8]) -> IResult<&[u8], ContentSize> {
-    map(
+fn octets_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], usize> {
+    ctx.field(
+        Construct::Octets,
+        s,
         alt((
-            map(tag_no_case("NIL"), |_| None),
-            map(
-                alt((
-                    map(tuple((digit1, tag(b" "), digit1)), |(x, _, y)| (x, Some(y))),
-                    map(digit1, |x| (x, None)),
-                )),
-                |x| Some(x),
-            ),
+            number_parser,
+            map(|i| lenient_nil(ctx, WarningKind::NilOctets, i), |_| 0),
         )),
-        |val| {
-            if let Some(size_tuple) = val {
-                let (left, right) = size_tuple;
-                let tmp_str = from_utf8(left).unwrap();
-                let left_size = str::parse::<usize>(tmp_str).unwrap();
-                let result = match right {
-                    Some(right_val) => {
-                        let tmp_str = from_utf8(right_val).unwrap();
-                        let right_size = str::parse::<usize>(tmp_str).unwrap();
-                        Some(right_size)
-                    }
-                    None => None,
-                };
-                ContentSize(Some(left_size), result)
-            } else {
-                ContentSize(None, None)
-            }
-        },
-    )(s)
+    )
+}
+
+/// body-fld-lines = number
+pub fn lines_parser(s: &[u8]) -> IResult<&[u8], u32> {
+    map_res(digit1, |x| str::parse::<u32>(from_utf8(x).unwrap()))(s)
 }
 
 #[derive(Debug, PartialEq)]
@@ -284,13 +741,17 @@ impl ContentMD5HeaderField {
 }
 
 pub fn content_md5_header_field_parser(s: &[u8]) -> IResult<&[u8], ContentMD5HeaderField> {
-    map(
-        alt((
-            map(tag_no_case(b"NIL"), |_| None),
-            map(double_quoted_string, |x| Some(x.to_vec())),
-        )),
-        |val| ContentMD5HeaderField { value: val },
-    )(s)
+    let (remain, value) = content_md5_header_field_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
+        ContentMD5HeaderField {
+            value: value.map(Cow::into_owned),
+        },
+    ))
+}
+
+fn content_md5_header_field_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], Option<Cow<'a, [u8]>>> {
+    ctx.field(Construct::ContentMD5, s, |i| nstring_ctx(ctx, i))
 }
 #[derive(Debug, PartialEq)]
 pub struct ContentDispositionHeaderField {
@@ -303,7 +764,7 @@ impl ContentDispositionHeaderField {
         let mut result = b"Content-Disposition: ".to_vec();
         if let Some(mut value) = self.value.clone() {
             result.append(&mut value);
-            for param in &self.parameters.list {
+            for param in &self.parameters {
                 result.extend_from_slice(b";\r\n");
                 result.extend_from_slice(b"        ");
                 result.extend(param.get_content_type_text().iter());
@@ -319,68 +780,97 @@ impl ContentDispositionHeaderField {
 pub fn content_disposition_header_field_parser(
     s: &[u8],
 ) -> IResult<&[u8], ContentDispositionHeaderField> {
-    map(
+    let (remain, disposition) = content_disposition_header_field_ctx(&Context::strict(s), s)?;
+    Ok((remain, borrowed::disposition_into_owned(disposition)))
+}
+
+fn content_disposition_header_field_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], Option<borrowed::Disposition<'a>>> {
+    ctx.field(
+        Construct::ContentDisposition,
+        s,
         alt((
             map(tag_no_case(b"NIL"), |_| None),
-            map(content_disposition_header_field_parser_0, |x| Some(x)),
+            map(|i| content_disposition_header_field_ctx_0(ctx, i), Some),
         )),
-        |disposition| {
-            if let Some(dispo) = disposition {
-                dispo
-            } else {
-                ContentDispositionHeaderField {
-                    value: None,
-                    parameters: Parameters { list: vec![] },
-                }
-            }
-        },
-    )(s)
+    )
 }
 
 pub fn content_disposition_header_field_parser_0(
     s: &[u8],
 ) -> IResult<&[u8], ContentDispositionHeaderField> {
+    let (remain, disposition) = content_disposition_header_field_ctx_0(&Context::strict(s), s)?;
+    Ok((remain, borrowed::disposition_into_owned(Some(disposition))))
+}
+
+fn content_disposition_header_field_ctx_0<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], borrowed::Disposition<'a>> {
     map(
         delimited(
             tag(b"("),
-            tuple((double_quoted_string, tag(b" "), parameters)),
+            tuple((
+                |i| string_ctx(ctx, i),
+                |i| sp(ctx, i),
+                |i| parameters_ctx(ctx, i),
+            )),
             tag(b")"),
         ),
-        |(value, _, params)| ContentDispositionHeaderField {
-            value: Some(value.to_vec()),
-            parameters: params,
-        },
+        |(value, _, parameters)| borrowed::Disposition { value, parameters },
     )(s)
 }
 
 // RFC 1766
 #[derive(Debug, PartialEq)]
 pub struct ContentLanguageHeaderField {
-    pub value: Option<Vec<u8>>,
+    pub value: Vec<Vec<u8>>,
 }
 
 impl ContentLanguageHeaderField {
     pub fn get_text(&self) -> Option<Vec<u8>> {
-        if let Some(value) = self.value.clone() {
-            let mut result = b"Content-Language: ".to_vec();
-            result.append(&mut value.to_vec());
-            result.extend_from_slice(b"\r\n");
-            return Some(result);
+        if self.value.is_empty() {
+            return None;
         }
-        None
+        let mut result = b"Content-Language: ".to_vec();
+        result.append(&mut self.value.join(b", ".as_ref()));
+        result.extend_from_slice(b"\r\n");
+        Some(result)
     }
 }
 
+/// body-fld-lang = nstring / "(" string *(SP string) ")"
 pub fn content_language_header_field_parser(
     s: &[u8],
 ) -> IResult<&[u8], ContentLanguageHeaderField> {
-    map(
+    let (remain, value) = content_language_header_field_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
+        ContentLanguageHeaderField {
+            value: value.into_iter().map(Cow::into_owned).collect(),
+        },
+    ))
+}
+
+fn content_language_header_field_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], Vec<Cow<'a, [u8]>>> {
+    ctx.field(
+        Construct::ContentLanguage,
+        s,
         alt((
-            map(tag_no_case(b"NIL"), |_| None),
-            map(double_quoted_string, |x| Some(x.to_vec())),
+            map(tag_no_case(b"NIL"), |_| vec![]),
+            map(|i| string_ctx(ctx, i), |x| vec![x]),
+            delimited(
+                tag(b"("),
+                separated_list1(|i| sp(ctx, i), |i| string_ctx(ctx, i)),
+                tag(b")"),
+            ),
         )),
-        |val| ContentLanguageHeaderField { value: val },
-    )(s)
+    )
 }
 
 // RFC 2557
@@ -401,134 +891,395 @@ impl ContentLocationHeaderField {
     }
 }
 
-pub fn content_location_header_field_parser(
-    s: &[u8],
-) -> IResult<&[u8], ContentLocationHeaderField> {
-    map(
+pub fn content_location_header_field_parser(s: &[u8]) -> IResult<&[u8], ContentLocationHeaderField> {
+    let (remain, value) = content_location_header_field_ctx(&Context::strict(s), s)?;
+    Ok((
+        remain,
+        ContentLocationHeaderField {
+            value: value.map(Cow::into_owned),
+        },
+    ))
+}
+
+fn content_location_header_field_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], Option<Cow<'a, [u8]>>> {
+    ctx.field(Construct::ContentLocation, s, |i| nstring_ctx(ctx, i))
+}
+/// Which FETCH data item a body came from. The non-extensible BODY form has no
+/// extension data (md5, disposition, language, location and the multipart parameters).
+///
+/// Extension data is optional in BODYSTRUCTURE too, so the form is only known
+/// for sure from the item name, see `ParserOptions::form`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BodyForm {
+    Body,
+    BodyStructure,
+}
+
+impl BodyForm {
+    /// The form named by a FETCH item, "BODY" or "BODYSTRUCTURE" in any case.
+    pub fn from_keyword(keyword: &[u8]) -> Option<BodyForm> {
+        if keyword.eq_ignore_ascii_case(b"BODY") {
+            Some(BodyForm::Body)
+        } else if keyword.eq_ignore_ascii_case(b"BODYSTRUCTURE") {
+            Some(BodyForm::BodyStructure)
+        } else {
+            None
+        }
+    }
+}
+
+/// body-extension = nstring / number / "(" body-extension *(SP body-extension) ")"
+///
+/// Future extension data that follows body-fld-loc. It is kept as it was sent.
+#[derive(Debug, PartialEq)]
+pub enum BodyExtension {
+    NString(Option<Vec<u8>>),
+    Number(u64),
+    List(Vec<BodyExtension>),
+}
+
+impl BodyExtension {
+    /// IMAP representation of the extension.
+    pub fn get_text(&self) -> Vec<u8> {
+        let mut result = vec![];
+        serializer::write_extension(&mut result, self);
+        result
+    }
+}
+
+pub fn body_extension_parser(s: &[u8]) -> IResult<&[u8], BodyExtension> {
+    let (remain, extension) = body_extension_ctx(&Context::strict(s), s)?;
+    Ok((remain, extension.into_owned()))
+}
+
+fn body_extension_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], borrowed::BodyExtension<'a>> {
+    ctx.field(
+        Construct::Extension,
+        s,
         alt((
-            map(tag_no_case(b"NIL"), |_| None),
-            map(double_quoted_string, |x| Some(x.to_vec())),
+            map(
+                map_res(digit1, |x| str::parse::<u64>(from_utf8(x).unwrap())),
+                borrowed::BodyExtension::Number,
+            ),
+            map(|i| nstring_ctx(ctx, i), borrowed::BodyExtension::NString),
+            map(
+                delimited(
+                    tag(b"("),
+                    separated_list1(|i| sp(ctx, i), |i| body_extension_ctx(ctx, i)),
+                    tag(b")"),
+                ),
+                borrowed::BodyExtension::List,
+            ),
+        )),
+    )
+}
+
+/// [SP body-fld-dsp [SP body-fld-lang [SP body-fld-loc *(SP body-extension)]]]
+/// at the end of any part.
+fn extension_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], borrowed::Extension<'a>> {
+    let sp = |i| sp(ctx, i);
+    map(
+        tuple((
+            opt(preceded(sp, |i| content_disposition_header_field_ctx(ctx, i))),
+            opt(preceded(sp, |i| content_language_header_field_ctx(ctx, i))),
+            opt(tuple((
+                sp,
+                |i| content_location_header_field_ctx(ctx, i),
+                |i| body_extensions_ctx(ctx, i),
+            ))),
+        )),
+        |(disposition, language, location)| {
+            let (content_location, extensions) = match location {
+                Some((_, location, extensions)) => (location, extensions),
+                None => (None, vec![]),
+            };
+            borrowed::Extension {
+                content_disposition: disposition.flatten(),
+                content_language: language.unwrap_or_default(),
+                content_location,
+                extensions,
+            }
+        },
+    )(s)
+}
+
+/// body-fields, which basic, text and message/rfc822 parts start with.
+fn fields_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], borrowed::Fields<'a>> {
+    let sp = |i| sp(ctx, i);
+    map(
+        tuple((
+            |i| content_type_header_field_ctx(ctx, i),
+            sp,
+            |i| content_id_header_field_ctx(ctx, i),
+            sp,
+            |i| content_description_header_field_ctx(ctx, i),
+            sp,
+            |i| content_transfer_encoding_header_field_ctx(ctx, i),
+        )),
+        |((ttype, subtype, parameters), _, content_id, _, content_description, _, encoding)| {
+            borrowed::Fields {
+                ttype,
+                subtype,
+                parameters,
+                content_id,
+                content_description,
+                content_transfer_encoding: encoding,
+            }
+        },
+    )(s)
+}
+
+pub fn body_extensions_parser(s: &[u8]) -> IResult<&[u8], Vec<BodyExtension>> {
+    let (remain, extensions) = body_extensions_ctx(&Context::strict(s), s)?;
+    Ok((remain, borrowed::extensions_into_owned(extensions)))
+}
+
+fn body_extensions_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], Vec<borrowed::BodyExtension<'a>>> {
+    many0(preceded(|i| sp(ctx, i), |i| body_extension_ctx(ctx, i)))(s)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SingleBody {
+    pub content_type: ContentTypeHeaderField,
+    pub content_id: ContentIDHeaderField,
+    pub content_description: ContentDescriptionHeaderField,
+    pub content_transfer_encoding: ContentTransferEncodingHeaderField,
+    pub octets: usize,
+    /// Size in text lines; only text/* parts have one.
+    pub lines: Option<u32>,
+    pub form: BodyForm,
+    pub content_md5: ContentMD5HeaderField,
+    pub content_disposition: ContentDispositionHeaderField,
+    pub content_language: ContentLanguageHeaderField,
+    pub content_location: ContentLocationHeaderField,
+    pub extensions: Vec<BodyExtension>,
+    pub data: Vec<u8>,
+    pub raw_header: Vec<u8>
+}
+
+impl SingleBody {
+    pub fn media_type(&self) -> &MediaType {
+        &self.content_type.ttype
+    }
+
+    pub fn set_data(&mut self, data: Vec<u8>) {
+        self.data = data;
+    }
+
+    pub fn set_raw_header(&mut self, data: Vec<u8>){
+        self.raw_header = data;
+    }
+
+    pub fn is_with_data(&self) -> bool {
+        !self.data.is_empty()
+    }
+
+    pub fn get_text(&self) -> Vec<u8> {
+        let mut full_text: Vec<u8> = vec![];
+        if !self.raw_header.is_empty() {
+            full_text.append(&mut self.raw_header.to_owned())
+        }
+        full_text.extend_from_slice(b"\r\n");
+        full_text.append(&mut self.content_type.get_text());
+        full_text.append(&mut self.content_id.get_text().unwrap_or(vec![]));
+        full_text.append(&mut self.content_description.get_text().unwrap_or(vec![]));
+        full_text.append(&mut self.content_transfer_encoding.get_text());
+        full_text.append(&mut self.content_md5.get_text().unwrap_or(vec![]));
+        full_text.append(&mut self.content_disposition.get_text().unwrap_or(vec![]));
+        full_text.append(&mut self.content_language.get_text().unwrap_or(vec![]));
+        full_text.append(&mut self.content_location.get_text().unwrap_or(vec![]));
+        full_text.extend_from_slice(b"\r\n");
+        full_text.append(&mut self.data.clone());
+        full_text.extend_from_slice(b"\r\n");
+        full_text
+    }
+}
+
+pub fn single_body_parser(s: &[u8]) -> IResult<&[u8], SingleBody> {
+    let (remain, single) = single_body_ctx(&Context::strict(s), s)?;
+    Ok((remain, single.into_owned()))
+}
+
+fn single_body_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], borrowed::SingleBody<'a>> {
+    let sp = |i| sp(ctx, i);
+    let (remain, (_, fields, _, octets)) =
+        tuple((tag(b"("), |i| fields_ctx(ctx, i), sp, |i| octets_ctx(ctx, i)))(s)?;
+    // Only body-type-text is followed by the size in lines (RFC 3501).
+    let (remain, lines) = if TopLevelType::from(fields.ttype.as_ref()) == TopLevelType::Text {
+        map(preceded(sp, |i| ctx.field(Construct::Lines, i, lines_parser)), Some)(remain)?
+    } else {
+        (remain, None)
+    };
+    let (remain, (md5, extension)) = terminated(
+        tuple((
+            opt(preceded(sp, |i| content_md5_header_field_ctx(ctx, i))),
+            |i| extension_ctx(ctx, i),
         )),
-        |val| ContentLocationHeaderField { value: val },
-    )(s)
+        tag(b")"),
+    )(remain)?;
+    Ok((
+        remain,
+        borrowed::SingleBody {
+            fields,
+            octets,
+            lines,
+            form: if md5.is_some() {
+                BodyForm::BodyStructure
+            } else {
+                BodyForm::Body
+            },
+            content_md5: md5.flatten(),
+            extension,
+        },
+    ))
 }
+
+/// A message/rfc822 part: the usual body fields followed by the envelope,
+/// the encapsulated body and the line count of the encapsulated message.
 #[derive(Debug, PartialEq)]
-pub struct SingleBody {
+pub struct MessageBody {
     pub content_type: ContentTypeHeaderField,
     pub content_id: ContentIDHeaderField,
     pub content_description: ContentDescriptionHeaderField,
     pub content_transfer_encoding: ContentTransferEncodingHeaderField,
-    pub content_size: ContentSize,
+    pub octets: usize,
+    pub envelope: Envelope,
+    pub body: Box<Body>,
+    pub lines: u32,
+    pub form: BodyForm,
     pub content_md5: ContentMD5HeaderField,
     pub content_disposition: ContentDispositionHeaderField,
     pub content_language: ContentLanguageHeaderField,
     pub content_location: ContentLocationHeaderField,
+    pub extensions: Vec<BodyExtension>,
     pub data: Vec<u8>,
-    pub raw_header: Vec<u8>
+    pub raw_header: Vec<u8>,
 }
 
-impl SingleBody {
-    pub fn set_data(&mut self, data: Vec<u8>) {
-        self.data = data;
+impl MessageBody {
+    pub fn media_type(&self) -> &MediaType {
+        &self.content_type.ttype
     }
 
-    pub fn set_raw_header(&mut self, data: Vec<u8>){
+    /// An empty 'Sequence' writes the whole encapsulated message into this part,
+    /// otherwise the 'Sequence' is resolved inside the encapsulated body.
+    pub fn set_data(&mut self, sequence: sequence::Sequence, data: Vec<u8>) -> bool {
+        if sequence.is_empty() {
+            self.data = data;
+            true
+        } else {
+            self.body.set_data(sequence, data)
+        }
+    }
+
+    pub fn set_raw_header(&mut self, data: Vec<u8>) {
         self.raw_header = data;
     }
 
     pub fn is_with_data(&self) -> bool {
-        !self.data.is_empty()
+        !self.data.is_empty() || self.body.are_all_bodies_with_data()
     }
 
     pub fn get_text(&self) -> Vec<u8> {
         let mut full_text: Vec<u8> = vec![];
         if !self.raw_header.is_empty() {
-            dbg!(123211 as i64);
             full_text.append(&mut self.raw_header.to_owned())
         }
         full_text.extend_from_slice(b"\r\n");
         full_text.append(&mut self.content_type.get_text());
-        full_text.append(&mut self.content_id.get_text().unwrap_or(vec![]));
-        full_text.append(&mut self.content_description.get_text().unwrap_or(vec![]));
+        full_text.append(&mut self.content_id.get_text().unwrap_or_default());
+        full_text.append(&mut self.content_description.get_text().unwrap_or_default());
         full_text.append(&mut self.content_transfer_encoding.get_text());
-        full_text.append(&mut self.content_md5.get_text().unwrap_or(vec![]));
-        full_text.append(&mut self.content_disposition.get_text().unwrap_or(vec![]));
-        full_text.append(&mut self.content_language.get_text().unwrap_or(vec![]));
-        full_text.append(&mut self.content_location.get_text().unwrap_or(vec![]));
+        full_text.append(&mut self.content_md5.get_text().unwrap_or_default());
+        full_text.append(&mut self.content_disposition.get_text().unwrap_or_default());
+        full_text.append(&mut self.content_language.get_text().unwrap_or_default());
+        full_text.append(&mut self.content_location.get_text().unwrap_or_default());
         full_text.extend_from_slice(b"\r\n");
-        full_text.append(&mut self.data.clone());
+        if self.data.is_empty() {
+            full_text.append(&mut self.envelope.get_text());
+            full_text.extend_from_slice(b"MIME-Version: 1.0\r\n");
+            let mut body_text = self.body.get_text();
+            // A single part starts with the CRLF that follows a boundary line,
+            // which would end the encapsulated header block too early.
+            if body_text.starts_with(b"\r\n") {
+                body_text.drain(..2);
+            }
+            full_text.append(&mut body_text);
+        } else {
+            full_text.append(&mut self.data.clone());
+        }
         full_text.extend_from_slice(b"\r\n");
         full_text
     }
 }
 
-pub fn single_body_parser(s: &[u8]) -> IResult<&[u8], SingleBody> {
+fn is_message_content_type(fields: &borrowed::Fields) -> bool {
+    media_type::is_encapsulated_message(&fields.ttype, &fields.subtype)
+}
+
+pub fn message_body_parser(s: &[u8]) -> IResult<&[u8], MessageBody> {
+    let ctx = Context::strict(s);
+    let (remain, mut message) = message_body_ctx(&ctx, s)?;
+    message.set_form(ctx.form(message.has_extension_data()));
+    Ok((remain, message.into_owned()))
+}
+
+fn message_body_ctx<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], borrowed::MessageBody<'a>> {
+    let sp = |i| sp(ctx, i);
     map(
-        tuple((
+        delimited(
             tag(b"("),
-            content_type_header_field_parser,
-            tag(b" "),
-            content_id_header_field_parser,
-            tag(b" "),
-            content_description_header_field_parser,
-            tag(b" "),
-            content_transfer_encoding_header_field_parser,
-            tag(b" "),
-            content_size_parser,
-            tag(b" "),
-            content_md5_header_field_parser,
-            tag(b" "),
-            content_disposition_header_field_parser,
-            opt(tuple((tag(b" "), content_language_header_field_parser))),
-            opt(tuple((tag(b" "), content_location_header_field_parser))),
+            tuple((
+                verify(|i| fields_ctx(ctx, i), is_message_content_type),
+                sp,
+                |i| octets_ctx(ctx, i),
+                tuple((
+                    sp,
+                    |i| ctx.field(Construct::Envelope, i, |i| envelope_ctx(ctx, i)),
+                    sp,
+                    |i| encapsulated_body_ctx(ctx, i),
+                    sp,
+                    |i| ctx.field(Construct::Lines, i, lines_parser),
+                )),
+                opt(preceded(sp, |i| content_md5_header_field_ctx(ctx, i))),
+                |i| extension_ctx(ctx, i),
+            )),
             tag(b")"),
-        )),
-        |(
-            _,
-            ttype,
-            _,
-            id,
-            _,
-            desc,
-            _,
-            encoding,
-            _,
-            size,
-            _,
-            md5,
-            _,
-            disposition,
-            language_opt,
-            location_opt,
-            _,
-        )| SingleBody {
-            content_type: ttype,
-            content_id: id,
-            content_description: desc,
-            content_transfer_encoding: encoding,
-            content_size: size,
-            content_md5: md5,
-            content_disposition: disposition,
-            content_language: if let Some((_, language)) = language_opt {
-                language
-            } else {
-                ContentLanguageHeaderField { value: None }
-            },
-            content_location: if let Some((_, location)) = location_opt {
-                location
-            } else {
-                ContentLocationHeaderField { value: None }
-            },
-            data: vec![],
-            raw_header: vec![],
+        ),
+        |(fields, _, octets, (_, envelope, _, body, _, lines), md5, extension)| {
+            borrowed::MessageBody {
+                fields,
+                octets,
+                envelope,
+                body: Box::new(body),
+                lines,
+                form: if md5.is_some() {
+                    BodyForm::BodyStructure
+                } else {
+                    BodyForm::Body
+                },
+                content_md5: md5.flatten(),
+                extension,
+            }
         },
     )(s)
 }
 
 #[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum Body {
     Single(SingleBody),
     Multi(MultiBody),
+    Message(MessageBody),
 }
 
 impl Body {
@@ -545,9 +1296,75 @@ impl Body {
             Body::Multi(body) => {
                 body.set_data(sequence, data)
             }
+            // A message/rfc822 at the top level is a non-multipart body, so its
+            // own section number is dropped before resolving the rest.
+            Body::Message(body) => {
+                let mut sequence = sequence;
+                sequence.pop();
+                body.set_data(sequence, data)
+            }
+        }
+    }
+
+    /// The part at an IMAP section number (RFC 3501 section 6.4.5). A
+    /// non-multipart body is part 1, and the parts of a message/rfc822 part
+    /// are those of its encapsulated body. Unlike `set_data`, a number that
+    /// goes on past a single part gives None; an empty one gives the body.
+    pub fn part(&self, sequence: &sequence::Sequence) -> Option<&Body> {
+        let path: Vec<usize> = sequence.0.iter().copied().collect();
+        match self {
+            Body::Multi(_) => self.descend(&path),
+            _ => match path.split_first() {
+                None => Some(self),
+                Some((1, rest)) => self.descend(rest),
+                Some(_) => None,
+            },
+        }
+    }
+
+    pub fn part_mut(&mut self, sequence: &sequence::Sequence) -> Option<&mut Body> {
+        let path: Vec<usize> = sequence.0.iter().copied().collect();
+        match self {
+            Body::Multi(_) => self.descend_mut(&path),
+            _ => match path.split_first() {
+                None => Some(self),
+                Some((1, rest)) => self.descend_mut(rest),
+                Some(_) => None,
+            },
         }
     }
 
+    /// The part at `path` below this one, which has already been selected.
+    fn descend(&self, path: &[usize]) -> Option<&Body> {
+        let Some((&index, rest)) = path.split_first() else {
+            return Some(self);
+        };
+        let parts = match self {
+            Body::Single(_) => return None,
+            Body::Multi(body) => &body.parts,
+            Body::Message(body) => match body.body.as_ref() {
+                Body::Multi(inner) => &inner.parts,
+                inner => return if index == 1 { inner.descend(rest) } else { None },
+            },
+        };
+        parts.get(index.checked_sub(1)?)?.descend(rest)
+    }
+
+    fn descend_mut(&mut self, path: &[usize]) -> Option<&mut Body> {
+        let Some((&index, rest)) = path.split_first() else {
+            return Some(self);
+        };
+        let parts = match self {
+            Body::Single(_) => return None,
+            Body::Multi(body) => &mut body.parts,
+            Body::Message(body) => match body.body.as_mut() {
+                Body::Multi(inner) => &mut inner.parts,
+                inner => return if index == 1 { inner.descend_mut(rest) } else { None },
+            },
+        };
+        parts.get_mut(index.checked_sub(1)?)?.descend_mut(rest)
+    }
+
     pub fn get_text(&self) -> Vec<u8> {
         match self {
             Body::Single(body) => {
@@ -556,6 +1373,9 @@ impl Body {
             Body::Multi(body) => {
                 body.get_text()
             }
+            Body::Message(body) => {
+                body.get_text()
+            }
         }
     }
 
@@ -567,6 +1387,38 @@ impl Body {
             Body::Multi(body) => {
                 body.are_all_bodies_with_data()
             }
+            Body::Message(body) => {
+                body.is_with_data()
+            }
+        }
+    }
+
+    pub fn media_type(&self) -> &MediaType {
+        match self {
+            Body::Single(body) => body.media_type(),
+            Body::Multi(body) => body.media_type(),
+            Body::Message(body) => body.media_type(),
+        }
+    }
+
+    pub fn raw_header(&self) -> &[u8] {
+        match self {
+            Body::Single(body) => &body.raw_header,
+            Body::Multi(body) => &body.raw_header,
+            Body::Message(body) => &body.raw_header,
+        }
+    }
+
+    /// The raw header with RFC 2047 encoded words decoded.
+    pub fn decoded_header(&self) -> String {
+        decode::decode_encoded_words(self.raw_header())
+    }
+
+    pub fn form(&self) -> BodyForm {
+        match self {
+            Body::Single(body) => body.form,
+            Body::Multi(body) => body.form,
+            Body::Message(body) => body.form,
         }
     }
 
@@ -578,6 +1430,9 @@ impl Body {
             Body::Multi(body) => {
                 body.raw_header.append(&mut data.clone());
             }
+            Body::Message(body) => {
+                body.raw_header.append(&mut data.clone());
+            }
         }
     }
 }
@@ -585,41 +1440,27 @@ impl Body {
 #[derive(Debug, PartialEq)]
 pub struct MultiBody {
     pub parts: Vec<Body>,
-    pub content_type: Vec<u8>,
+    /// "multipart" and the subtype as the server spelled it.
+    pub content_type: MediaType,
+    pub form: BodyForm,
     pub parameters: Parameters,
+    pub content_disposition: ContentDispositionHeaderField,
+    pub content_language: ContentLanguageHeaderField,
+    pub content_location: ContentLocationHeaderField,
+    pub extensions: Vec<BodyExtension>,
     pub raw_header: Vec<u8>,
 }
 
 impl MultiBody {
+    pub fn media_type(&self) -> &MediaType {
+        &self.content_type
+    }
+
     /// If the 'body' is only single, it will write data regardless of what the 'Sequence' is. 
     /// If the currently found 'body' is single and the 'Sequence' is not empty, 
     /// it will not continue searching and will directly write the data. 
     /// So, if '1.1' is a single body, '1.1.2' or '1.1.1.1.1.1' will both be written in this body.
     pub fn set_data(&mut self, mut sequence: sequence::Sequence, data: Vec<u8>) -> bool {
-        // if sequence.len() == 1 {
-        //     let tmp_idx = sequence.pop().unwrap();
-        //     if self.parts.len() < tmp_idx {
-        //         return false
-        //     } else {
-        //         if let Body::Single(ref mut body) = self.parts[tmp_idx - 1] {
-        //             body.set_data(data);
-        //             return true
-        //         } else {
-        //             return false
-        //         }
-        //     }
-        // } else {
-        //     let tmp_idx = sequence.pop().unwrap();
-        //     if self.parts.len() < tmp_idx {
-        //         return false
-        //     } else {
-        //         if let Body::Multi(ref mut body) = self.parts[tmp_idx - 1] {
-        //             body.set_data(sequence, data)
-        //         } else {
-        //             return false
-        //         }
-        //     }
-        // }
         if sequence.is_empty() {
             false
         } else {
@@ -631,6 +1472,8 @@ impl MultiBody {
                 true
             } else if let Body::Multi(ref mut body) = self.parts[tmp_idx - 1]{
                 body.set_data(sequence, data)
+            } else if let Body::Message(ref mut body) = self.parts[tmp_idx - 1] {
+                body.set_data(sequence, data)
             } else {
                 false
             }
@@ -643,16 +1486,25 @@ impl MultiBody {
             result.append(&mut self.raw_header.to_owned())
         }
         result.append(&mut b"Content-Type: ".to_vec());
-        let mut boundary :Vec<u8> = vec![];
-        result.append(&mut b"multipart/".to_vec());
-        result.append(&mut self.content_type.clone());
-        for param in &self.parameters.list {
+        let boundary = self.parameters.get(b"boundary").unwrap_or_default().into_owned();
+        result.append(&mut self.content_type.get_content_type_text());
+        for param in &self.parameters {
             result.extend_from_slice(b";\r\n");
             result.extend_from_slice(b"        ");
             result.extend(param.get_content_type_text().iter());
-            if param.attribute.clone().to_ascii_lowercase() == b"boundary" {
-                boundary = param.value.clone();
-            }
+        }
+        for mut header in [
+            self.content_disposition.get_text(),
+            self.content_language.get_text(),
+            self.content_location.get_text(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            // Every header line here ends where the next one starts, like Content-Type above.
+            header.truncate(header.len() - 2);
+            result.extend_from_slice(b"\r\n");
+            result.append(&mut header);
         }
         for part in self.parts.iter() {
             result.extend_from_slice(b"\r\n");
@@ -683,33 +1535,149 @@ impl MultiBody {
 }
 
 pub fn body_parser(s: &[u8]) -> IResult<&[u8], Body> {
-    alt((
-        map(single_body_parser, Body::Single),
-        map(multi_body_parser, Body::Multi),
-    ))(s)
+    let (remain, body) = body_ctx(&Context::strict(s), s)?;
+    Ok((remain, body.into_owned()))
+}
+
+/// `body_parser` with `options`, returning the repairs made in lenient mode.
+pub fn body_parser_with_options<'a>(
+    s: &'a [u8],
+    options: &ParserOptions,
+) -> IResult<&'a [u8], ParsedBody> {
+    let ctx = Context::new(*options, s);
+    let (remain, body) = body_ctx(&ctx, s)?;
+    Ok((
+        remain,
+        ParsedBody {
+            body: body.into_owned(),
+            warnings: ctx.into_warnings(),
+        },
+    ))
+}
+
+/// Parses a body like `body_parser_with_options`, reporting which construct
+/// of which part failed.
+pub fn parse_body(s: &[u8], options: &ParserOptions) -> Result<ParsedBody, Error> {
+    let (body, warnings) = borrowed::parse_body(s, options)?;
+    Ok(ParsedBody {
+        body: body.into_owned(),
+        warnings,
+    })
+}
+
+pub(crate) fn body_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], borrowed::Body<'a>> {
+    let (remain, mut body) = alt((
+        // message/rfc822 goes first: its envelope would otherwise be taken as
+        // extension data of a basic single part in the BODY form.
+        |i| body_alternative(ctx, i, false, map(|i| message_body_ctx(ctx, i), borrowed::Body::Message)),
+        |i| body_alternative(ctx, i, false, map(|i| single_body_ctx(ctx, i), borrowed::Body::Single)),
+        |i| body_alternative(ctx, i, true, map(|i| multi_body_ctx(ctx, i), borrowed::Body::Multi)),
+    ))(s)?;
+    if ctx.is_outermost() {
+        body.set_form(ctx.form(body.has_extension_data()));
+    }
+    Ok((remain, body))
+}
+
+fn body_alternative<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+    multipart: bool,
+    mut parser: impl FnMut(&'a [u8]) -> IResult<&'a [u8], borrowed::Body<'a>>,
+) -> IResult<&'a [u8], borrowed::Body<'a>> {
+    if ctx.is_outermost() {
+        ctx.single_root.set(!multipart);
+    }
+    ctx.field(Construct::Body, s, |i| backtrack(ctx, i, &mut parser))
+}
+
+/// The body of a message/rfc822 part.
+fn encapsulated_body_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], borrowed::Body<'a>> {
+    ctx.path.borrow_mut().push(None);
+    let result = body_ctx(ctx, s);
+    ctx.path.borrow_mut().pop();
+    result
+}
+
+/// 1*body, numbering each part in the context.
+fn multi_body_parts_0<'a>(
+    ctx: &Context,
+    s: &'a [u8],
+) -> IResult<&'a [u8], Vec<borrowed::Body<'a>>> {
+    // The parts of a multipart inside a message/rfc822 part take the place
+    // of the encapsulated body in the section numbers.
+    let encapsulated = matches!(ctx.path.borrow().last(), Some(None));
+    if !encapsulated {
+        ctx.path.borrow_mut().push(None);
+    }
+    let mut parts = vec![];
+    let mut remain = s;
+    let result = loop {
+        *ctx.path.borrow_mut().last_mut().unwrap() = Some(parts.len() + 1);
+        match body_ctx(ctx, remain) {
+            Ok((rest, part)) => {
+                parts.push(part);
+                remain = rest;
+            }
+            Err(NomErr::Error(_)) => break Ok((remain, parts)),
+            Err(e) => break Err(e),
+        }
+    };
+    if encapsulated {
+        *ctx.path.borrow_mut().last_mut().unwrap() = None;
+    } else {
+        ctx.path.borrow_mut().pop();
+    }
+    result
+}
+
+/// 1*body SP, or in lenient mode no body at all.
+fn multi_body_parts<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], Vec<borrowed::Body<'a>>> {
+    let (remain, parts) = multi_body_parts_0(ctx, s)?;
+    if parts.is_empty() {
+        if !ctx.lenient() {
+            return Err(NomErr::Error(nom::error::Error::new(s, ErrorKind::Many1)));
+        }
+        ctx.warn(WarningKind::EmptyMultipart, s);
+        let (remain, _) = opt(|i| sp(ctx, i))(remain)?;
+        return Ok((remain, parts));
+    }
+    let (remain, _) = sp(ctx, remain)?;
+    Ok((remain, parts))
 }
 
 pub fn multi_body_parser(s: &[u8]) -> IResult<&[u8], MultiBody> {
+    let ctx = Context::strict(s);
+    let (remain, mut multi) = multi_body_ctx(&ctx, s)?;
+    multi.set_form(ctx.form(multi.has_extension_data()));
+    Ok((remain, multi.into_owned()))
+}
+
+fn multi_body_ctx<'a>(ctx: &Context, s: &'a [u8]) -> IResult<&'a [u8], borrowed::MultiBody<'a>> {
+    let sp = |i| sp(ctx, i);
     map(
         delimited(
             tag(b"("),
             tuple((
-                many1(body_parser),
-                tag(b" "),
-                double_quoted_string,
-                tag(b" "),
-                parameters,
-                opt(tuple((tag(b" "), tag_no_case("NIL")))),
-                opt(tuple((tag(b" "), tag_no_case("NIL")))),
-                opt(tuple((tag(b" "), tag_no_case("NIL")))),
+                |i| multi_body_parts(ctx, i),
+                |i| ctx.field(Construct::MultipartSubtype, i, |i| string_ctx(ctx, i)),
+                opt(preceded(sp, |i| {
+                    ctx.field(Construct::MultipartParameters, i, |i| parameters_ctx(ctx, i))
+                })),
+                |i| extension_ctx(ctx, i),
             )),
             tag(b")"),
         ),
-        |(parts, _, content_type, _, parameters, _, _, _)| MultiBody {
-            parts: parts,
-            content_type: content_type.to_vec(),
-            parameters: parameters,
-            raw_header: vec![],
+        |(parts, subtype, parameters, extension)| borrowed::MultiBody {
+            parts,
+            subtype,
+            form: if parameters.is_some() {
+                BodyForm::BodyStructure
+            } else {
+                BodyForm::Body
+            },
+            parameters: parameters.unwrap_or_default(),
+            extension,
         },
     )(s)
 }
@@ -734,6 +1702,57 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_double_quoted_string_2() {
+        assert_eq!(
+            double_quoted_string(br#""report \"final\".pdf" NIL"#),
+            Ok((b" NIL".as_ref(), br#"report \"final\".pdf"#.as_ref()))
+        );
+        assert_eq!(
+            quoted(br#""report \"final\".pdf""#).unwrap().1,
+            br#"report "final".pdf"#.as_ref()
+        );
+        assert_eq!(quoted(br#""C:\\dir\\""#).unwrap().1, br#"C:\dir\"#.as_ref());
+        assert!(double_quoted_string(br#""bad \escape""#).is_err());
+        assert_eq!(escape_quoted(br#"a "b" \c"#), br#"a \"b\" \\c"#);
+    }
+    #[test]
+    fn test_parameter_with_escaped_quotes() {
+        let param = parameter(br#""name" "report \"final\".pdf""#).unwrap().1;
+        assert_eq!(
+            param,
+            Parameter {
+                attribute: b"name".to_vec(),
+                value: br#"report "final".pdf"#.to_vec()
+            }
+        );
+        assert_eq!(param.get_content_type_text(), br#"name="report \"final\".pdf""#);
+        let body = single_body_parser(br#"("application" "pdf" ("name" "report \"final\".pdf") NIL NIL "base64" 1024 NIL ("attachment" ("filename" "report \"final\".pdf")) NIL NIL)"#).unwrap().1;
+        assert_eq!(
+            body.content_disposition.parameters.list[0].value,
+            br#"report "final".pdf"#
+        );
+    }
+    #[test]
+    fn test_literal_1() {
+        assert_eq!(
+            literal(b"{12}\r\nsome \"thing\" rest"),
+            Ok((b" rest".as_ref(), b"some \"thing\"".as_ref()))
+        );
+        assert_eq!(
+            literal(b"~{3}\r\n\x00\xff) rest"),
+            Ok((b" rest".as_ref(), b"\x00\xff)".as_ref()))
+        );
+        assert_eq!(
+            literal(b"{3+}\r\nabc"),
+            Ok((b"".as_ref(), b"abc".as_ref()))
+        );
+        assert!(literal(b"{5}\r\nabc").is_err());
+        assert_eq!(string(b"{3}\r\nabc"), Ok((b"".as_ref(), Cow::Borrowed(b"abc".as_ref()))));
+        assert_eq!(nstring(b"NIL"), Ok((b"".as_ref(), None)));
+        assert_eq!(nstring(b"\"NIL\""), Ok((b"".as_ref(), Some(Cow::Borrowed(b"NIL".as_ref())))));
+    }
+
     #[test]
     fn test_content_type_1() {
         assert_eq!(
@@ -782,6 +1801,39 @@ mod tests {
         )
     }
     #[test]
+    fn test_parameters_lookup() {
+        let mut params = parameters(br#"("CHARSET" "utf-8" "Filename*1" "part2.pdf" "filename*0" "long-" "x" "1" "X" "2")"#)
+            .unwrap()
+            .1;
+        assert_eq!(params.get(b"charset").as_deref(), Some(b"utf-8".as_ref()));
+        assert_eq!(params.get(b"FILENAME").as_deref(), Some(b"long-part2.pdf".as_ref()));
+        assert_eq!(params.get(b"file"), None);
+        assert_eq!(params.get_all(b"x"), vec![b"1".as_ref(), b"2".as_ref()]);
+        params.set(b"filename", b"short.pdf");
+        assert_eq!(params.list[1].attribute, b"filename".to_vec());
+        assert_eq!(params.get(b"filename").as_deref(), Some(b"short.pdf".as_ref()));
+        assert_eq!(params.len(), 4);
+        assert_eq!(params.remove(b"X"), Some(b"1".to_vec()));
+        params.set(b"name", b"n");
+        let names: Vec<&[u8]> = params.iter().map(|x| x.attribute.as_slice()).collect();
+        assert_eq!(names, vec![b"CHARSET".as_ref(), b"filename".as_ref(), b"name".as_ref()]);
+        let params = parameters(br#"("name" "fallback.rar" "name*0*" "utf-8''%E5%AF%86" "NAME*1" ".rar" "title" "plain")"#)
+            .unwrap()
+            .1;
+        assert_eq!(params.get_decoded(b"name").unwrap().value, "密.rar");
+        assert_eq!(params.get_decoded(b"title").unwrap().value, "plain");
+        assert_eq!(params.get_decoded(b"missing"), None);
+        let params = parameters(br#"("name" "=?utf-8?B?5a+G56CB?=.rar")"#).unwrap().1;
+        assert_eq!(params.get_decoded(b"name").unwrap().value, "密码.rar");
+        let description = ContentDescriptionHeaderField {
+            value: Some(b"=?ISO-8859-1?Q?Caf=E9?=".to_vec()),
+        };
+        assert_eq!(description.get_decoded(), Some("Café".to_string()));
+        assert_eq!(continuation_section(b"name*", b"name"), Some(0));
+        assert_eq!(continuation_section(b"name*2*", b"name"), Some(2));
+        assert_eq!(continuation_section(b"names*2", b"name"), None);
+    }
+    #[test]
     fn test_get_content_type_text_1() {
         assert_eq!(
             Parameter {
@@ -917,28 +1969,30 @@ mod tests {
         assert_eq!(res.get_text(), b"Content-Transfer-Encoding: base64\r\n")
     }
     #[test]
-    fn test_content_size_1() {
-        assert_eq!(
-            content_size_parser(b"1234").unwrap().1,
-            ContentSize(Some(1234), None)
-        );
-        assert_eq!(
-            content_size_parser(b"nil").unwrap().1,
-            ContentSize(None, None)
-        );
-        assert_eq!(
-            content_size_parser(b"1417 36").unwrap().1,
-            ContentSize(Some(1417), Some(36))
-        );
-    }
-    #[test]
-    fn test_content_size_2() {
-        assert_eq!(content_size_parser(b"1234").unwrap().1.get_text(), b"1234");
-        assert_eq!(content_size_parser(b"nil").unwrap().1.get_text(), b"0");
-        assert_eq!(
-            content_size_parser(b"1417 36").unwrap().1.get_text(),
-            b"1417 36"
-        );
+    fn test_octets_and_lines() {
+        assert_eq!(octets_parser(b"1234").unwrap(), (b"".as_ref(), 1234));
+        assert!(octets_parser(b"nil").is_err());
+        let lenient = Context::new(ParserOptions { lenient: true, ..Default::default() }, b"nil");
+        assert_eq!(octets_ctx(&lenient, b"nil").unwrap().1, 0);
+        assert_eq!(lenient.into_warnings()[0].kind, WarningKind::NilOctets);
+        assert_eq!(lines_parser(b"36 NIL").unwrap(), (b" NIL".as_ref(), 36));
+
+        let text = single_body_parser(br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1417 36)"#).unwrap().1;
+        assert_eq!((text.octets, text.lines), (1417, Some(36)));
+        // A number-like md5 after the size of a non-text part is not a line count.
+        let image = body_parser_with_options(
+            br#"("IMAGE" "PNG" NIL NIL NIL "BASE64" 1417 36 NIL NIL NIL)"#,
+            &ParserOptions { lenient: true, ..Default::default() },
+        )
+        .unwrap()
+        .1;
+        let Body::Single(image) = image.body else {
+            panic!("expected a single part");
+        };
+        assert_eq!((image.octets, image.lines), (1417, None));
+        assert_eq!(image.content_md5.value, Some(b"36".to_vec()));
+        assert!(single_body_parser(br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1417)"#).is_err());
+        assert!(single_body_parser(br#"("IMAGE" "PNG" NIL NIL NIL "BASE64" 1417 36)"#).is_err());
     }
     #[test]
     fn test_md5_header_field() {
@@ -1034,12 +2088,34 @@ mod tests {
                 .unwrap()
                 .1,
             ContentLanguageHeaderField {
-                value: Some(b"en-cockney".to_vec())
+                value: vec![b"en-cockney".to_vec()]
             }
         );
         assert_eq!(
             content_language_header_field_parser(b"NIL").unwrap().1,
-            ContentLanguageHeaderField { value: None }
+            ContentLanguageHeaderField { value: vec![] }
+        );
+        assert_eq!(
+            content_language_header_field_parser(br#"("en" "de-CH")"#).unwrap().1,
+            ContentLanguageHeaderField {
+                value: vec![b"en".to_vec(), b"de-CH".to_vec()]
+            }
+        );
+        assert_eq!(
+            content_language_header_field_parser(br#"("en" "de-CH")"#)
+                .unwrap()
+                .1
+                .get_text(),
+            Some(b"Content-Language: en, de-CH\r\n".to_vec())
+        );
+        assert_eq!(
+            single_body_parser(br#"("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1 NIL NIL ("en" "de") NIL)"#)
+                .unwrap()
+                .1
+                .content_language,
+            ContentLanguageHeaderField {
+                value: vec![b"en".to_vec(), b"de".to_vec()]
+            }
         );
     }
     #[test]
@@ -1088,7 +2164,9 @@ mod tests {
                 content_transfer_encoding: ContentTransferEncodingHeaderField {
                     value: b"QUOTED-PRINTABLE".to_vec()
                 },
-                content_size: ContentSize(Some(4692), Some(69)),
+                octets: 4692,
+                lines: Some(69),
+                form: BodyForm::BodyStructure,
                 content_md5: ContentMD5HeaderField {
                     value: None
                 },
@@ -1096,8 +2174,9 @@ mod tests {
                     value: None,
                     parameters: Parameters { list: vec![] }
                 },
-                content_language: ContentLanguageHeaderField { value: None },
+                content_language: ContentLanguageHeaderField { value: vec![] },
                 content_location: ContentLocationHeaderField { value: None },
+                extensions: vec![],
                 data: vec![],
                 raw_header: vec![],
             }
@@ -1127,7 +2206,9 @@ mod tests {
                 content_transfer_encoding: ContentTransferEncodingHeaderField {
                     value: b"base64".to_vec()
                 },
-                content_size: ContentSize(Some(66628), None),
+                octets: 66628,
+                lines: None,
+                form: BodyForm::BodyStructure,
                 content_md5: ContentMD5HeaderField {
                     value: None
                 },
@@ -1140,8 +2221,9 @@ mod tests {
                         }
                     ] }
                 },
-                content_language: ContentLanguageHeaderField { value: None },
+                content_language: ContentLanguageHeaderField { value: vec![] },
                 content_location: ContentLocationHeaderField { value: None },
+                extensions: vec![],
                 data: vec![],
                 raw_header: vec![]
             }
@@ -1172,7 +2254,9 @@ mod tests {
                 content_transfer_encoding: ContentTransferEncodingHeaderField {
                     value: b"base64".to_vec()
                 },
-                content_size: ContentSize(Some(66628), None),
+                octets: 66628,
+                lines: None,
+                form: BodyForm::BodyStructure,
                 content_md5: ContentMD5HeaderField {
                     value: None
                 },
@@ -1185,14 +2269,24 @@ mod tests {
                         }
                     ] }
                 },
-                content_language: ContentLanguageHeaderField { value: None },
+                content_language: ContentLanguageHeaderField { value: vec![] },
                 content_location: ContentLocationHeaderField { value: None },
+                extensions: vec![],
                 data: vec![],
                 raw_header: vec![],
             }
         );
     }
     #[test]
+    fn test_single_body_with_literals() {
+        let body = single_body_parser(b"(\"application\" \"pdf\" (\"name\" {14}\r\nreport \"a\".pdf) NIL {6}\r\nline\r\n \"base64\" 1024 NIL (\"attachment\" (\"filename\" ~{10}\r\nr\xe9sum\xe9.pdf)) NIL NIL)").unwrap();
+        assert_eq!(body.0, b"");
+        let body = body.1;
+        assert_eq!(body.content_type.parameters.list[0].value, b"report \"a\".pdf");
+        assert_eq!(body.content_description.value, Some(b"line\r\n".to_vec()));
+        assert_eq!(body.content_disposition.parameters.list[0].value, b"r\xe9sum\xe9.pdf");
+    }
+    #[test]
     fn test_body_parser() {
         assert_eq!(
         body_parser(br#"(("TEXT" "PLAIN" ("CHARSET" "UTF-8") NIL NIL "7BIT" 2279 48 NIL ("INLINE" NIL) NIL)("TEXT" "HTML" ("CHARSET" "UTF-8") NIL NIL "QUOTED-PRINTABLE" 3421 67 NIL ("INLINE" NIL) NIL) "ALTERNATIVE" ("BOUNDARY" "----=_Part_526379_2891879.1572458037114") NIL)"#).unwrap().1,
@@ -1218,7 +2312,9 @@ mod tests {
                         content_transfer_encoding: ContentTransferEncodingHeaderField {
                             value: b"7BIT".to_vec()
                         },
-                        content_size: ContentSize(Some(2279), Some(48)),
+                        octets: 2279,
+                        lines: Some(48),
+                        form: BodyForm::BodyStructure,
                         content_md5: ContentMD5HeaderField {
                             value: None
                         },
@@ -1227,8 +2323,9 @@ mod tests {
                             parameters: Parameters { list: vec![
                             ] }
                         },
-                        content_language: ContentLanguageHeaderField { value: None },
+                        content_language: ContentLanguageHeaderField { value: vec![] },
                         content_location: ContentLocationHeaderField { value: None },
+                        extensions: vec![],
                         data: vec![],
                         raw_header: vec![],
                     }),
@@ -1252,7 +2349,9 @@ mod tests {
                         content_transfer_encoding: ContentTransferEncodingHeaderField {
                             value: b"QUOTED-PRINTABLE".to_vec()
                         },
-                        content_size: ContentSize(Some(3421), Some(67)),
+                        octets: 3421,
+                        lines: Some(67),
+                        form: BodyForm::BodyStructure,
                         content_md5: ContentMD5HeaderField {
                             value: None
                         },
@@ -1261,19 +2360,28 @@ mod tests {
                             parameters: Parameters { list: vec![
                             ] }
                         },
-                        content_language: ContentLanguageHeaderField { value: None },
+                        content_language: ContentLanguageHeaderField { value: vec![] },
                         content_location: ContentLocationHeaderField { value: None },
+                        extensions: vec![],
                         data: vec![],
                         raw_header: vec![],
                     })
                 ],
-                content_type: b"ALTERNATIVE".to_vec(),
+                content_type: MediaType::new(b"multipart", b"ALTERNATIVE"),
+                form: BodyForm::BodyStructure,
                 parameters: Parameters {
                     list: vec![Parameter {
                         attribute: b"BOUNDARY".to_vec(),
                         value: b"----=_Part_526379_2891879.1572458037114".to_vec()
                     }]
                 },
+                content_disposition: ContentDispositionHeaderField {
+                    value: None,
+                    parameters: Parameters { list: vec![] },
+                },
+                content_language: ContentLanguageHeaderField { value: vec![] },
+                content_location: ContentLocationHeaderField { value: None },
+                extensions: vec![],
                 raw_header: vec![]
             })
         );
@@ -1300,7 +2408,9 @@ mod tests {
                         content_transfer_encoding: ContentTransferEncodingHeaderField {
                             value: b"base64".to_vec()
                         },
-                        content_size: ContentSize(Some(1188), Some(16)),
+                        octets: 1188,
+                        lines: Some(16),
+                        form: BodyForm::BodyStructure,
                         content_md5: ContentMD5HeaderField {
                             value: None
                         },
@@ -1309,19 +2419,28 @@ mod tests {
                             parameters: Parameters { list: vec![
                             ] }
                         },
-                        content_language: ContentLanguageHeaderField { value: None },
+                        content_language: ContentLanguageHeaderField { value: vec![] },
                         content_location: ContentLocationHeaderField { value: None },
+                        extensions: vec![],
                         data: vec![],
                         raw_header: vec![],
                     }),
                 ],
-                content_type: b"mixed".to_vec(),
+                content_type: MediaType::new(b"multipart", b"mixed"),
+                form: BodyForm::BodyStructure,
                 parameters: Parameters {
                     list: vec![Parameter {
                         attribute: b"boundary".to_vec(),
                         value: b"===============1522363357941492443==".to_vec()
                     }]
                 },
+                content_disposition: ContentDispositionHeaderField {
+                    value: None,
+                    parameters: Parameters { list: vec![] },
+                },
+                content_language: ContentLanguageHeaderField { value: vec![] },
+                content_location: ContentLocationHeaderField { value: None },
+                extensions: vec![],
                 raw_header: vec![],
             })
         );
@@ -1349,7 +2468,9 @@ mod tests {
                             content_transfer_encoding: ContentTransferEncodingHeaderField {
                                 value: b"base64".to_vec()
                             },
-                            content_size: ContentSize(Some(84), Some(2)),
+                            octets: 84,
+                            lines: Some(2),
+                            form: BodyForm::BodyStructure,
                             content_md5: ContentMD5HeaderField {
                                 value: None
                             },
@@ -1358,8 +2479,9 @@ mod tests {
                                 parameters: Parameters { list: vec![
                                 ] }
                             },
-                            content_language: ContentLanguageHeaderField { value: None },
+                            content_language: ContentLanguageHeaderField { value: vec![] },
                             content_location: ContentLocationHeaderField { value: None },
+                            extensions: vec![],
                             data: vec![],
                             raw_header: vec![],
                         }),
@@ -1383,7 +2505,9 @@ mod tests {
                             content_transfer_encoding: ContentTransferEncodingHeaderField {
                                 value: b"quoted-printable".to_vec()
                             },
-                            content_size: ContentSize(Some(629), Some(8)),
+                            octets: 629,
+                            lines: Some(8),
+                            form: BodyForm::BodyStructure,
                             content_md5: ContentMD5HeaderField {
                                 value: None
                             },
@@ -1392,19 +2516,28 @@ mod tests {
                                 parameters: Parameters { list: vec![
                                 ] }
                             },
-                            content_language: ContentLanguageHeaderField { value: None },
+                            content_language: ContentLanguageHeaderField { value: vec![] },
                             content_location: ContentLocationHeaderField { value: None },
+                            extensions: vec![],
                             data: vec![],
                             raw_header: vec![],
                         }),
                     ],
-                    content_type: b"alternative".to_vec(),
+                    content_type: MediaType::new(b"multipart", b"alternative"),
+                    form: BodyForm::BodyStructure,
                     parameters: Parameters {
                         list: vec![Parameter {
                             attribute: b"boundary".to_vec(),
                             value: b"----=_002_NextPart034528600178_=----".to_vec()
                         }]
                     },
+                    content_disposition: ContentDispositionHeaderField {
+                        value: None,
+                        parameters: Parameters { list: vec![] },
+                    },
+                    content_language: ContentLanguageHeaderField { value: vec![] },
+                    content_location: ContentLocationHeaderField { value: None },
+                    extensions: vec![],
                     raw_header: vec![]
                 }),
                 Body::Single(SingleBody {
@@ -1427,7 +2560,9 @@ mod tests {
                     content_transfer_encoding: ContentTransferEncodingHeaderField {
                         value: b"base64".to_vec()
                     },
-                    content_size: ContentSize(Some(664200), None),
+                    octets: 664200,
+                    lines: None,
+                    form: BodyForm::BodyStructure,
                     content_md5: ContentMD5HeaderField {
                         value: None
                     },
@@ -1440,18 +2575,26 @@ mod tests {
                             }
                         ] }
                     },
-                    content_language: ContentLanguageHeaderField { value: None },
+                    content_language: ContentLanguageHeaderField { value: vec![] },
                     content_location: ContentLocationHeaderField { value: None },
+                    extensions: vec![],
                     data: vec![],
                     raw_header: vec![],
                 })
 
-            ], content_type: b"mixed".to_vec(), parameters: Parameters {
+            ], content_type: MediaType::new(b"multipart", b"mixed"), form: BodyForm::BodyStructure, parameters: Parameters {
                 list: vec![Parameter{
                     attribute: b"boundary".to_vec(),
                     value: b"----=_001_NextPart655111288810_=----".to_vec()
                 }] 
             },
+                content_disposition: ContentDispositionHeaderField {
+                    value: None,
+                    parameters: Parameters { list: vec![] },
+                },
+                content_language: ContentLanguageHeaderField { value: vec![] },
+                content_location: ContentLocationHeaderField { value: None },
+                extensions: vec![],
                 raw_header: vec![] })
         );
     }
@@ -1482,7 +2625,9 @@ mod tests {
             content_transfer_encoding: ContentTransferEncodingHeaderField {
                 value: b"8BIT".to_vec()
             },
-            content_size: ContentSize(Some(393), Some(9)),
+            octets: 393,
+            lines: Some(9),
+            form: BodyForm::BodyStructure,
             content_md5: ContentMD5HeaderField {
                 value: None
             },
@@ -1491,8 +2636,9 @@ mod tests {
                 parameters: Parameters { list: vec![
                 ] }
             },
-            content_language: ContentLanguageHeaderField { value: None },
+            content_language: ContentLanguageHeaderField { value: vec![] },
             content_location: ContentLocationHeaderField { value: None },
+            extensions: vec![],
             data: vec![],
             raw_header: vec![],
         });
@@ -1512,31 +2658,31 @@ mod tests {
         let mut multi_body = body_parser(br#"((("text" "plain" ("charset" "GB2312") NIL NIL "base64" 84 2 NIL NIL NIL NIL)("text" "html" ("charset" "GB2312") NIL NIL "quoted-printable" 629 8 NIL NIL NIL NIL) "alternative" ("boundary" "----=_002_NextPart034528600178_=----") NIL NIL NIL)("application" "octet-stream" ("name" "FB679764.tar") NIL NIL "base64" 664200 NIL ("attachment" ("filename" "FB679764.tar")) NIL NIL) "mixed" ("boundary" "----=_001_NextPart655111288810_=----") NIL NIL NIL)"#).unwrap().1;
         let data = b"test".to_vec();
         let set_result = multi_body.set_data(sequence::Sequence::new(b"1.1").unwrap(), data.clone());
-        assert_eq!(set_result, true);
+        assert!(set_result);
         let set_result_2 = multi_body.set_data(sequence::Sequence::new(b"1.3").unwrap(), data);
-        assert_eq!(set_result_2, false);
+        assert!(!set_result_2);
     }
     #[test]
     fn test_set_data_in_multi_body_2() {
         let mut multi_body = body_parser(br#"((("text" "plain" ("charset" "GB2312") NIL NIL "base64" 84 2 NIL NIL NIL NIL)("text" "html" ("charset" "GB2312") NIL NIL "quoted-printable" 629 8 NIL NIL NIL NIL) "alternative" ("boundary" "----=_002_NextPart034528600178_=----") NIL NIL NIL)("application" "octet-stream" ("name" "FB679764.tar") NIL NIL "base64" 664200 NIL ("attachment" ("filename" "FB679764.tar")) NIL NIL) "mixed" ("boundary" "----=_001_NextPart655111288810_=----") NIL NIL NIL)"#).unwrap().1;
         let data = b"test".to_vec();
         let set_result = multi_body.set_data(sequence::Sequence::new(b"1.1.32432").unwrap(), data.clone());
-        assert_eq!(set_result, true);
+        assert!(set_result);
         let set_result_2 = multi_body.set_data(sequence::Sequence::new(b"1.2.2.2").unwrap(), data.clone());
-        assert_eq!(set_result_2, true);
+        assert!(set_result_2);
         let set_result_3 = multi_body.set_data(sequence::Sequence::new(b"2.2").unwrap(), data);
-        assert_eq!(set_result_3, true);
-        assert_eq!(multi_body.are_all_bodies_with_data(), true);
+        assert!(set_result_3);
+        assert!(multi_body.are_all_bodies_with_data());
     }
     #[test]
     fn test_bodies_are_all_have_data() {
         let mut body = body_parser(br#"((("text" "plain" ("charset" "GB2312") NIL NIL "base64" 84 2 NIL NIL NIL NIL)("text" "html" ("charset" "GB2312") NIL NIL "quoted-printable" 629 8 NIL NIL NIL NIL) "alternative" ("boundary" "----=_002_NextPart034528600178_=----") NIL NIL NIL)("application" "octet-stream" ("name" "FB679764.tar") NIL NIL "base64" 664200 NIL ("attachment" ("filename" "FB679764.tar")) NIL NIL) "mixed" ("boundary" "----=_001_NextPart655111288810_=----") NIL NIL NIL)"#).unwrap().1;
         let data = b"test".to_vec();
         body.set_data(sequence::Sequence::new(b"1.1").unwrap(), data.clone());
-        assert_eq!(body.are_all_bodies_with_data(), false);
+        assert!(!body.are_all_bodies_with_data());
         body.set_data(sequence::Sequence::new(b"1.2").unwrap(), data.clone());
         body.set_data(sequence::Sequence::new(b"2").unwrap(), data.clone());
-        assert_eq!(body.are_all_bodies_with_data(), true);
+        assert!(body.are_all_bodies_with_data());
     }
     #[test]
     fn test_body_get_text() {
@@ -1545,4 +2691,258 @@ mod tests {
         multi_body.set_data(Sequence::new(b"1.2").unwrap(), b"I am coming!!!!".to_vec());
         println!("{:?}", multi_body.get_text());
     }
+    #[test]
+    fn test_message_body_parser() {
+        let body = body_parser(br#"(("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1 NIL NIL NIL NIL)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 ("Tue, 05 Dec 2023 06:16:58 +0000" "Original" (("Alice" NIL "alice" "example.com")) (("Alice" NIL "alice" "example.com")) (("Alice" NIL "alice" "example.com")) ((NIL NIL "bob" "example.org")) NIL NIL NIL "<1@example.com>") ("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 8 1 NIL NIL NIL NIL) 12 NIL ("attachment" NIL) NIL NIL) "MIXED" ("BOUNDARY" "b1") NIL NIL NIL)"#).unwrap().1;
+        let Body::Multi(multi) = body else {
+            panic!("expected a multipart body");
+        };
+        let Body::Message(message) = &multi.parts[1] else {
+            panic!("expected a message/rfc822 part");
+        };
+        assert_eq!(message.octets, 342);
+        assert_eq!(message.lines, 12);
+        assert_eq!(message.envelope.subject, Some(b"Original".to_vec()));
+        assert_eq!(message.content_disposition.value, Some(b"attachment".to_vec()));
+        let Body::Single(inner) = message.body.as_ref() else {
+            panic!("expected a single encapsulated body");
+        };
+        assert_eq!((inner.octets, inner.lines), (8, Some(1)));
+    }
+    #[test]
+    fn test_body_part() {
+        let mut body = body_parser(br#"(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) (("TEXT" "PLAIN" NIL NIL NIL "7BIT" 8 1)("IMAGE" "GIF" NIL NIL NIL "BASE64" 20) "MIXED") 12)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 100 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) ("TEXT" "HTML" NIL NIL NIL "7BIT" 8 1) 5) "MIXED")"#).unwrap().1;
+        let media_type = |body: &Body, section: &[u8]| {
+            body.part(&Sequence::new(section).unwrap()).map(|x| x.media_type().essence())
+        };
+        assert_eq!(media_type(&body, b"1").as_deref(), Some("text/plain"));
+        assert_eq!(media_type(&body, b"2").as_deref(), Some("message/rfc822"));
+        assert_eq!(media_type(&body, b"2.2").as_deref(), Some("image/gif"));
+        assert_eq!(media_type(&body, b"3.1").as_deref(), Some("text/html"));
+        assert_eq!(media_type(&body, b"3.2"), None);
+        assert_eq!(media_type(&body, b"1.1"), None);
+        assert_eq!(media_type(&body, b"1.1.32432"), None);
+        assert_eq!(media_type(&body, b"4"), None);
+        assert_eq!(media_type(&body, b"0"), None);
+        assert_eq!(body.part(&Sequence(Default::default())), Some(&body));
+        let Some(Body::Single(gif)) = body.part_mut(&Sequence::new(b"2.2").unwrap()) else {
+            panic!("expected a single part");
+        };
+        gif.set_data(b"R0lG".to_vec());
+        let Some(Body::Single(gif)) = body.part(&Sequence::new(b"2.2").unwrap()) else {
+            panic!("expected a single part");
+        };
+        assert_eq!(gif.data, b"R0lG".to_vec());
+
+        let single = body_parser(br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1)"#).unwrap().1;
+        assert_eq!(single.part(&Sequence::new(b"1").unwrap()), Some(&single));
+        assert_eq!(single.part(&Sequence::new(b"2").unwrap()), None);
+        assert_eq!(single.part(&Sequence::new(b"1.1").unwrap()), None);
+    }
+    #[test]
+    fn test_media_type_of_parts() {
+        let body = body_parser(br#"(("text" "plain" NIL NIL NIL "7BIT" 12 1)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) ("TEXT" "PLAIN" NIL NIL NIL "7BIT" 8 1) 12) "MIXED")"#).unwrap().1;
+        let media_type = body.media_type();
+        assert!(media_type.is_multipart());
+        assert_eq!(media_type, &MediaType::new(b"multipart", b"mixed"));
+        assert_eq!(media_type.get_content_type_text(), b"multipart/MIXED".to_vec());
+        let Body::Multi(multi) = body else {
+            panic!("expected a multipart body");
+        };
+        assert_eq!(multi.parts[0].media_type(), &MediaType::new(b"TEXT", b"PLAIN"));
+        assert!(multi.parts[1].media_type().is_encapsulated_message());
+    }
+    #[test]
+    fn test_message_body_set_data_and_get_text() {
+        let mut body = body_parser(br#"(("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1 NIL NIL NIL NIL)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 ("Tue, 05 Dec 2023 06:16:58 +0000" "Original" (("Alice" NIL "alice" "example.com")) NIL NIL ((NIL NIL "bob" "example.org")) NIL NIL NIL NIL) ("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 8 1 NIL NIL NIL NIL) 12) "MIXED" ("BOUNDARY" "b1") NIL NIL NIL)"#).unwrap().1;
+        assert!(body.set_data(Sequence::new(b"1").unwrap(), b"Outer".to_vec()));
+        assert!(!body.are_all_bodies_with_data());
+        assert!(body.set_data(Sequence::new(b"2.1").unwrap(), b"Inner".to_vec()));
+        assert!(body.are_all_bodies_with_data());
+        assert_eq!(
+            body.get_text(),
+            b"Content-Type: multipart/MIXED;\r\n        BOUNDARY=\"b1\"\r\n--b1\r\nContent-Type: TEXT/PLAIN;\r\n        CHARSET=\"utf-8\"\r\nContent-Transfer-Encoding: 7BIT\r\n\r\nOuter\r\n\r\n--b1\r\nContent-Type: MESSAGE/RFC822\r\nContent-Transfer-Encoding: 7BIT\r\n\r\nDate: Tue, 05 Dec 2023 06:16:58 +0000\r\nSubject: Original\r\nFrom: Alice <alice@example.com>\r\nTo: bob@example.org\r\nMIME-Version: 1.0\r\nContent-Type: TEXT/PLAIN;\r\n        CHARSET=\"utf-8\"\r\nContent-Transfer-Encoding: 7BIT\r\n\r\nInner\r\n\r\n\r\n--b1\r\n"
+        );
+    }
+    #[test]
+    fn test_multi_body_extension_data() {
+        let body = multi_body_parser(br#"(("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1 NIL NIL NIL NIL) "MIXED" ("BOUNDARY" "b1") ("inline" NIL) "en" "http://example.com/a")"#).unwrap().1;
+        assert_eq!(
+            body.content_disposition,
+            ContentDispositionHeaderField {
+                value: Some(b"inline".to_vec()),
+                parameters: Parameters { list: vec![] }
+            }
+        );
+        assert_eq!(body.content_language, ContentLanguageHeaderField { value: vec![b"en".to_vec()] });
+        assert_eq!(
+            body.content_location,
+            ContentLocationHeaderField { value: Some(b"http://example.com/a".to_vec()) }
+        );
+        assert!(body.get_text().starts_with(b"Content-Type: multipart/MIXED;\r\n        BOUNDARY=\"b1\"\r\nContent-Disposition: inline\r\nContent-Language: en\r\nContent-Location: http://example.com/a\r\n--b1\r\n"));
+        assert!(multi_body_parser(br#"(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1 NIL NIL) "MIXED" ("BOUNDARY" "b1") ("attachment" ("filename" "a.txt")))"#).is_ok());
+    }
+    #[test]
+    fn test_body_extensions() {
+        assert_eq!(
+            body_extension_parser(br#"("x-a" (1 NIL) "b\"c")"#).unwrap().1,
+            BodyExtension::List(vec![
+                BodyExtension::NString(Some(b"x-a".to_vec())),
+                BodyExtension::List(vec![BodyExtension::Number(1), BodyExtension::NString(None)]),
+                BodyExtension::NString(Some(br#"b"c"#.to_vec())),
+            ])
+        );
+        assert_eq!(
+            body_extension_parser(br#"("x-a" (1 NIL) "b\"c")"#).unwrap().1.get_text(),
+            br#"("x-a" (1 NIL) "b\"c")"#
+        );
+        let single = single_body_parser(br#"("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1 NIL NIL NIL NIL 42 ("x" NIL))"#).unwrap().1;
+        assert_eq!(
+            single.extensions,
+            vec![
+                BodyExtension::Number(42),
+                BodyExtension::List(vec![
+                    BodyExtension::NString(Some(b"x".to_vec())),
+                    BodyExtension::NString(None)
+                ])
+            ]
+        );
+        let multi = multi_body_parser(br#"(("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1 NIL NIL NIL NIL) "MIXED" ("BOUNDARY" "b1") NIL NIL NIL "future")"#).unwrap().1;
+        assert_eq!(multi.extensions, vec![BodyExtension::NString(Some(b"future".to_vec()))]);
+    }
+    #[test]
+    fn test_non_extensible_body_form() {
+        let text = br#"BODY (("TEXT" "PLAIN" ("CHARSET" "US-ASCII") NIL NIL "7BIT" 1152 23)("TEXT" "PLAIN" ("CHARSET" "US-ASCII" "NAME" "cc.diff") "<960723163407.20117h@cac.washington.edu>" "Compiler diff" "BASE64" 4554 73) "MIXED")"#;
+        let (body_text_within_parentheses, head) = head_bodystructure(text).unwrap();
+        assert_eq!(head, b"BODY");
+        let body = body_parser(body_text_within_parentheses).unwrap().1;
+        assert_eq!(body.form(), BodyForm::Body);
+        let Body::Multi(multi) = body else {
+            panic!("expected a multipart body");
+        };
+        assert_eq!(multi.parameters, Parameters { list: vec![] });
+        let Body::Single(second) = &multi.parts[1] else {
+            panic!("expected a single part");
+        };
+        assert_eq!(second.form, BodyForm::Body);
+        assert_eq!((second.octets, second.lines), (4554, Some(73)));
+        assert_eq!(second.content_description.value, Some(b"Compiler diff".to_vec()));
+        assert_eq!(second.content_md5, ContentMD5HeaderField { value: None });
+
+        let message = body_parser(br#"("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 (NIL "Original" NIL NIL NIL NIL NIL NIL NIL NIL) ("TEXT" "PLAIN" NIL NIL NIL "7BIT" 8 1) 12)"#).unwrap().1;
+        assert_eq!(message.form(), BodyForm::Body);
+        let extensible = body_parser(br#"("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "8BIT" 393 9 NIL NIL NIL)"#).unwrap().1;
+        assert_eq!(extensible.form(), BodyForm::BodyStructure);
+        // One part with extension data makes the whole body BODYSTRUCTURE.
+        let mixed = multi_body_parser(br#"(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1 1)("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1 1 NIL) "MIXED")"#).unwrap().1;
+        assert_eq!(mixed.form, BodyForm::BodyStructure);
+        assert!(mixed.parts.iter().all(|x| x.form() == BodyForm::BodyStructure));
+        let options = ParserOptions {
+            form: BodyForm::from_keyword(b"bodystructure"),
+            ..Default::default()
+        };
+        let parsed = body_parser_with_options(br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1 1)"#, &options).unwrap().1;
+        assert_eq!(parsed.body.form(), BodyForm::BodyStructure);
+    }
+
+    #[test]
+    fn test_lenient_options() {
+        let lenient = ParserOptions { lenient: true, ..Default::default() };
+        let text = br#"("TEXT"  "PLAIN" ("CHARSET" NIL) NIL NIL NIL 12 1)"#;
+        assert!(body_parser(text).is_err());
+        let parsed = body_parser_with_options(text, &lenient).unwrap().1;
+        assert_eq!(
+            parsed.warnings,
+            vec![
+                ParseWarning { kind: WarningKind::ExtraWhitespace, offset: 7 },
+                ParseWarning { kind: WarningKind::NilParameterValue, offset: 28 },
+                ParseWarning { kind: WarningKind::NilTransferEncoding, offset: 41 },
+            ]
+        );
+        let Body::Single(single) = parsed.body else {
+            panic!("expected a single part");
+        };
+        assert_eq!(single.content_type.parameters.list[0].value, b"".to_vec());
+        assert_eq!(single.content_transfer_encoding.value, b"7BIT".to_vec());
+
+        let text = br#"(TEXT PLAIN ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1)"#;
+        assert!(body_parser(text).is_err());
+        let parsed = body_parser_with_options(text, &lenient).unwrap().1;
+        assert_eq!(
+            parsed.warnings,
+            vec![
+                ParseWarning { kind: WarningKind::UnquotedAtom, offset: 1 },
+                ParseWarning { kind: WarningKind::UnquotedAtom, offset: 6 },
+            ]
+        );
+        let quoted_text = br#"("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1)"#;
+        assert_eq!(body_parser(quoted_text).unwrap().1, parsed.body);
+
+        let text = br#"("MIXED" ("BOUNDARY" "b1") NIL NIL)"#;
+        assert!(body_parser(text).is_err());
+        let parsed = body_parser_with_options(text, &lenient).unwrap().1;
+        assert_eq!(
+            parsed.warnings,
+            vec![ParseWarning { kind: WarningKind::EmptyMultipart, offset: 1 }]
+        );
+        let Body::Multi(multi) = parsed.body else {
+            panic!("expected a multipart body");
+        };
+        assert!(multi.parts.is_empty());
+        assert_eq!(multi.content_type.subtype, b"MIXED".to_vec());
+
+        // Conformant input is parsed the same way and without warnings.
+        let text = br#"(("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1 NIL NIL NIL NIL) "MIXED" ("BOUNDARY" "b1") NIL NIL NIL)"#;
+        let parsed = body_parser_with_options(text, &lenient).unwrap().1;
+        assert_eq!(parsed.warnings, vec![]);
+        assert_eq!(parsed.body, body_parser(text).unwrap().1);
+        let strict = body_parser_with_options(text, &ParserOptions::default()).unwrap().1;
+        assert_eq!(strict.body, parsed.body);
+    }
+    #[test]
+    fn test_parse_body_error() {
+        let options = ParserOptions::default();
+        let text = br#"(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1)(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1 NIL ("attachment" ("filename")) NIL NIL)("TEXT" "HTML" NIL NIL NIL "7BIT" 12 1) "ALTERNATIVE") "MIXED")"#;
+        let error = parse_body(text, &options).unwrap_err();
+        assert_eq!(
+            error,
+            Error::Body {
+                construct: Construct::ContentDisposition,
+                section: vec![2, 1],
+                offset: 111,
+                snippet: br#")) NIL NIL)("TEXT" "HTML" NIL NI"#.to_vec(),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            r#"invalid disposition of part 2.1 at offset 111 near ")) NIL NIL)(\"TEXT\" \"HTML\" NIL NI""#
+        );
+
+        // Parts of a message/rfc822 part are numbered from the message part.
+        let text = br#"(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) (("TEXT" "PLAIN" NIL NIL NIL "7BIT" 8 1)("TEXT" "PLAIN" NIL NIL NIL 7BIT 8 1) "MIXED") 12) "MIXED")"#;
+        let Error::Body { construct, section, .. } = parse_body(text, &options).unwrap_err() else {
+            panic!("expected a body error");
+        };
+        assert_eq!((construct, section), (Construct::ContentTransferEncoding, vec![2, 2]));
+        let text = br#"("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) ("TEXT" "PLAIN" NIL NIL NIL "7BIT" x 1) 12)"#;
+        let Error::Body { construct, section, .. } = parse_body(text, &options).unwrap_err() else {
+            panic!("expected a body error");
+        };
+        assert_eq!((construct, section), (Construct::Octets, vec![1, 1]));
+        // A non-multipart outermost body is part 1.
+        let text = br#"("TEXT" "PLAIN" NIL NIL NIL 7BIT 12 1)"#;
+        let error = parse_body(text, &options).unwrap_err();
+        let Error::Body { construct, ref section, .. } = error else {
+            panic!("expected a body error");
+        };
+        assert_eq!((construct, section.clone()), (Construct::ContentTransferEncoding, vec![1]));
+        assert!(error.to_string().starts_with("invalid transfer encoding of part 1 "));
+
+        let text = br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1"#;
+        let Error::Body { construct, offset, .. } = parse_body(text, &options).unwrap_err() else {
+            panic!("expected a body error");
+        };
+        assert_eq!((construct, offset), (Construct::Body, text.len()));
+        assert!(parse_body(br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1)"#, &options).is_ok());
+    }
 }
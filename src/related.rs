@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+
+use crate::attachment::leaf;
+use crate::decode::{decode_transfer_encoding, percent_decode};
+use crate::parser::{Body, MultiBody};
+
+/// The parts of a multipart/related (RFC 2387) sorted into the root, the
+/// resources the root refers to, and the parts nothing refers to. Parts
+/// are given by their index in `MultiBody::parts`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Related<'a> {
+    multi: &'a MultiBody,
+    pub root: usize,
+    /// Referenced parts by URL: `cid:` and the normalized Content-ID, and
+    /// the Content-Location made absolute when there is a base.
+    pub resources: HashMap<Vec<u8>, usize>,
+    pub unreferenced: Vec<usize>,
+    /// What relative URLs in the root are resolved against.
+    base: Option<Vec<u8>>,
+}
+
+/// Index of the root of a multipart/related: the part named by its "start"
+/// parameter, else the first one (RFC 2387).
+pub(crate) fn related_root(related: &MultiBody) -> usize {
+    related
+        .parameters
+        .get(b"start")
+        .and_then(|start| {
+            let start = normalize_id(&start);
+            related.parts.iter().position(|part| {
+                leaf(part)
+                    .and_then(|x| x.content_id)
+                    .is_some_and(|id| normalize_id(id) == start)
+            })
+        })
+        .unwrap_or(0)
+}
+
+/// A Content-ID without its angle brackets and with the domain lowercased,
+/// as message ids compare (RFC 2392).
+fn normalize_id(id: &[u8]) -> Vec<u8> {
+    let id = id.trim_ascii();
+    let id = id.strip_prefix(b"<").and_then(|x| x.strip_suffix(b">")).unwrap_or(id);
+    match id.iter().rposition(|&x| x == b'@') {
+        Some(at) => {
+            let mut result = id[..at + 1].to_vec();
+            result.extend(id[at + 1..].to_ascii_lowercase());
+            result
+        }
+        None => id.to_vec(),
+    }
+}
+
+fn cid_key(id: &[u8]) -> Vec<u8> {
+    let mut key = b"cid:".to_vec();
+    key.extend(normalize_id(id));
+    key
+}
+
+fn is_name_char(x: u8) -> bool {
+    x.is_ascii_alphanumeric() || x == b'-' || x == b'_'
+}
+
+/// The value that starts at `text[start]`, quoted or up to whitespace or
+/// one of `ends`.
+fn url_value<'a>(text: &'a [u8], start: usize, ends: &[u8]) -> &'a [u8] {
+    let rest = &text[start..];
+    match rest.first() {
+        Some(&quote @ (b'"' | b'\'')) => {
+            let rest = &rest[1..];
+            &rest[..rest.iter().position(|&x| x == quote).unwrap_or(rest.len())]
+        }
+        _ => {
+            let end = rest
+                .iter()
+                .position(|x| x.is_ascii_whitespace() || ends.contains(x))
+                .unwrap_or(rest.len());
+            &rest[..end]
+        }
+    }
+}
+
+/// The URLs in the root's text: values of the src, href and background
+/// attributes and of CSS url(), with `&amp;` unescaped.
+fn referenced_urls(text: &[u8]) -> Vec<Vec<u8>> {
+    let mut urls = vec![];
+    let mut index = 0;
+    while index < text.len() {
+        if index > 0 && is_name_char(text[index - 1]) {
+            index += 1;
+            continue;
+        }
+        let rest = &text[index..];
+        let starts = |name: &[u8]| rest.len() >= name.len() && rest[..name.len()].eq_ignore_ascii_case(name);
+        let value = if starts(b"url(") {
+            let start = index + 4;
+            let start = start + text[start..].iter().take_while(|x| x.is_ascii_whitespace()).count();
+            Some(url_value(text, start, b")"))
+        } else if let Some(name) = [b"src".as_ref(), b"href", b"background"].into_iter().find(|x| starts(x)) {
+            let mut start = index + name.len();
+            start += text[start..].iter().take_while(|x| x.is_ascii_whitespace()).count();
+            if text.get(start) == Some(&b'=') {
+                start += 1;
+                start += text[start..].iter().take_while(|x| x.is_ascii_whitespace()).count();
+                Some(url_value(text, start, b">"))
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        if let Some(value) = value.map(<[u8]>::trim_ascii).filter(|x| !x.is_empty()) {
+            urls.push(replace(value, b"&amp;", b"&"));
+        }
+        index += 1;
+    }
+    urls
+}
+
+fn replace(s: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(at) = rest.windows(from.len()).position(|x| x == from) {
+        result.extend_from_slice(&rest[..at]);
+        result.extend_from_slice(to);
+        rest = &rest[at + from.len()..];
+    }
+    result.extend_from_slice(rest);
+    result
+}
+
+fn cid(url: &[u8]) -> Option<&[u8]> {
+    url.get(..4)
+        .filter(|x| x.eq_ignore_ascii_case(b"cid:"))
+        .map(|_| &url[4..])
+}
+
+/// Length of the scheme and its ":" if `uri` is absolute.
+fn scheme_length(uri: &[u8]) -> Option<usize> {
+    let colon = uri.iter().position(|&x| x == b':')?;
+    let scheme = &uri[..colon];
+    let valid = scheme.first().is_some_and(u8::is_ascii_alphabetic)
+        && scheme
+            .iter()
+            .all(|&x| x.is_ascii_alphanumeric() || matches!(x, b'+' | b'-' | b'.'));
+    valid.then_some(colon + 1)
+}
+
+/// Removes the "." and ".." segments of a path (RFC 3986 5.2.4).
+fn remove_dot_segments(path: &[u8]) -> Vec<u8> {
+    let mut segments: Vec<&[u8]> = vec![];
+    let parts: Vec<&[u8]> = path.split(|&x| x == b'/').collect();
+    for (index, segment) in parts.iter().enumerate() {
+        let last = index + 1 == parts.len();
+        match *segment {
+            b"." | b".." => {
+                if *segment == b".." && segments.len() > 1 {
+                    segments.pop();
+                }
+                if last {
+                    segments.push(b"");
+                }
+            }
+            _ => segments.push(segment),
+        }
+    }
+    segments.join(&b'/')
+}
+
+/// Resolves a URI reference against an absolute base URI (RFC 3986 5.2).
+fn resolve_uri(base: &[u8], reference: &[u8]) -> Vec<u8> {
+    if scheme_length(reference).is_some() {
+        return reference.to_vec();
+    }
+    let Some(scheme) = scheme_length(base) else {
+        return reference.to_vec();
+    };
+    // The base without its query and fragment.
+    let base = &base[..base.iter().position(|x| b"?#".contains(x)).unwrap_or(base.len())];
+    let authority_end = if base[scheme..].starts_with(b"//") {
+        scheme + 2 + base[scheme + 2..].iter().position(|&x| x == b'/').unwrap_or(base.len() - scheme - 2)
+    } else {
+        scheme
+    };
+    let mut result;
+    if reference.starts_with(b"//") {
+        result = base[..scheme].to_vec();
+        result.extend_from_slice(reference);
+    } else if reference.starts_with(b"/") {
+        result = base[..authority_end].to_vec();
+        result.extend(remove_dot_segments(reference));
+    } else if reference.is_empty() || reference.starts_with(b"?") || reference.starts_with(b"#") {
+        result = base.to_vec();
+        result.extend_from_slice(reference);
+    } else {
+        let path = &base[authority_end..];
+        let directory = &path[..path.iter().rposition(|&x| x == b'/').map_or(0, |x| x + 1)];
+        let mut merged = if directory.is_empty() && authority_end > scheme {
+            b"/".to_vec()
+        } else {
+            directory.to_vec()
+        };
+        merged.extend_from_slice(reference);
+        result = base[..authority_end].to_vec();
+        result.extend(remove_dot_segments(&merged));
+    }
+    result
+}
+
+/// A location resolved against `base` when there is one; without a base a
+/// relative location only matches the same relative text (RFC 2557).
+fn absolute(location: &[u8], base: Option<&[u8]>) -> Vec<u8> {
+    match base {
+        Some(base) => resolve_uri(base, location),
+        None => location.to_vec(),
+    }
+}
+
+/// Decoded text of the root, or None if none of it has been fetched.
+fn root_text(root: &Body) -> Option<Vec<u8>> {
+    let mut text = vec![];
+    for (_, part) in root.parts() {
+        if let Body::Single(single) = part {
+            if single.media_type().is_text() && single.is_with_data() {
+                text.extend_from_slice(&decode_transfer_encoding(
+                    &single.content_transfer_encoding.value,
+                    &single.data,
+                ));
+            }
+        }
+    }
+    (!text.is_empty()).then_some(text)
+}
+
+impl<'a> Related<'a> {
+    /// Sorts the parts of `multi`, or None if it is not a multipart/related.
+    /// When the text of the root has not been fetched, every part with a
+    /// Content-ID or Content-Location is taken to be referenced.
+    pub fn new(multi: &'a MultiBody) -> Option<Self> {
+        if !multi.content_type.is(b"multipart", b"related") || multi.parts.is_empty() {
+            return None;
+        }
+        let root = related_root(multi);
+        // RFC 2557: parts resolve their locations against the multipart's,
+        // and the root's URLs against the root's own location if it has one.
+        let multi_base = multi.content_location.value.as_deref().map(<[u8]>::trim_ascii);
+        let root_base = leaf(&multi.parts[root])
+            .and_then(|x| x.location)
+            .map(|x| absolute(x.trim_ascii(), multi_base))
+            .or(multi_base.map(<[u8]>::to_vec));
+        let urls = root_text(&multi.parts[root]).map(|x| referenced_urls(&x));
+        let ids: Option<Vec<Vec<u8>>> = urls.as_ref().map(|urls| {
+            urls.iter()
+                .filter_map(|x| cid(x))
+                .map(|x| normalize_id(&percent_decode(x)))
+                .collect()
+        });
+        let locations: Option<Vec<Vec<u8>>> = urls.as_ref().map(|urls| {
+            urls.iter()
+                .filter(|x| cid(x).is_none())
+                .map(|x| absolute(x, root_base.as_deref()))
+                .collect()
+        });
+        let mut resources = HashMap::new();
+        let mut unreferenced = vec![];
+        for (index, part) in multi.parts.iter().enumerate() {
+            if index == root {
+                continue;
+            }
+            let Some(leaf) = leaf(part) else {
+                unreferenced.push(index);
+                continue;
+            };
+            let id = leaf.content_id.map(normalize_id);
+            let location = leaf.location.map(|x| absolute(x.trim_ascii(), multi_base));
+            let by_id = id
+                .as_ref()
+                .is_some_and(|id| ids.as_ref().is_none_or(|ids| ids.contains(id)));
+            let by_location = location
+                .as_ref()
+                .is_some_and(|x| locations.as_ref().is_none_or(|locations| locations.contains(x)));
+            if by_id {
+                resources.insert(cid_key(leaf.content_id.unwrap_or_default()), index);
+            }
+            if by_location {
+                resources.insert(location.unwrap_or_default(), index);
+            }
+            if !by_id && !by_location {
+                unreferenced.push(index);
+            }
+        }
+        Some(Related {
+            multi,
+            root,
+            resources,
+            unreferenced,
+            base: root_base,
+        })
+    }
+
+    pub fn root_part(&self) -> &'a Body {
+        &self.multi.parts[self.root]
+    }
+
+    /// Index of the part a URL in the root refers to, either a `cid:` URL
+    /// or a Content-Location, relative to the root's location.
+    pub fn resolve_index(&self, url: &[u8]) -> Option<usize> {
+        let url = url.trim_ascii();
+        match cid(url) {
+            Some(id) => self.resources.get(&cid_key(&percent_decode(id))).copied(),
+            None => self.resources.get(&absolute(url, self.base.as_deref())).copied(),
+        }
+    }
+
+    /// The part a URL in the root refers to.
+    pub fn resolve(&self, url: &[u8]) -> Option<&'a Body> {
+        self.resolve_index(url).map(|x| &self.multi.parts[x])
+    }
+
+    /// Whether the root refers to the part at `index`.
+    pub fn is_referenced(&self, index: usize) -> bool {
+        index != self.root && !self.unreferenced.contains(&index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::body_parser;
+    use crate::sequence::Sequence;
+
+    fn related(body: &Body) -> Related<'_> {
+        match body {
+            Body::Multi(multi) => Related::new(multi).unwrap(),
+            _ => panic!("not a multipart"),
+        }
+    }
+
+    #[test]
+    fn test_related() {
+        let mut body = body_parser(br#"(("IMAGE" "PNG" NIL "<Logo@Example.COM>" NIL "BASE64" 100 NIL NIL NIL NIL)("TEXT" "HTML" NIL "<root@x>" NIL "7BIT" 40 1 NIL NIL NIL NIL)("IMAGE" "GIF" NIL NIL NIL "BASE64" 60 NIL NIL NIL "http://example.com/a.gif")("IMAGE" "PNG" NIL "<unused@x>" NIL "BASE64" 200 NIL NIL NIL NIL)("APPLICATION" "PDF" NIL NIL NIL "BASE64" 300 NIL NIL NIL NIL) "RELATED" ("TYPE" "text/html" "START" "<root@X>") NIL NIL NIL)"#).unwrap().1;
+        // Without the root's text every part with an id counts as referenced.
+        let resolver = related(&body);
+        assert_eq!(resolver.root, 1);
+        assert_eq!(resolver.resources.len(), 3);
+        assert_eq!(resolver.unreferenced, vec![4]);
+        body.set_data(
+            Sequence::new(b"2").unwrap(),
+            br#"<img src="cid:Logo%40example.com"><img src='http://example.com/a.gif'>"#.to_vec(),
+        );
+        let resolver = related(&body);
+        assert_eq!(resolver.root_part().media_type().essence(), "text/html");
+        assert_eq!(resolver.resolve_index(b"cid:Logo@EXAMPLE.com"), Some(0));
+        assert_eq!(resolver.resolve_index(b"cid:logo@example.com"), None);
+        assert_eq!(resolver.resolve_index(b"http://example.com/a.gif"), Some(2));
+        assert_eq!(resolver.resolve(b"cid:unused@x"), None);
+        assert_eq!(resolver.unreferenced, vec![3, 4]);
+        assert!(resolver.is_referenced(0));
+        assert!(!resolver.is_referenced(1));
+    }
+
+    #[test]
+    fn test_mhtml_locations() {
+        let mut body = body_parser(br#"(("TEXT" "HTML" NIL NIL NIL "7BIT" 40 1 NIL NIL NIL "pages/index.html")("IMAGE" "GIF" NIL NIL NIL "BASE64" 60 NIL NIL NIL "pages/img/a.gif")("IMAGE" "GIF" NIL NIL NIL "BASE64" 60 NIL NIL NIL "http://example.com/style.css")("IMAGE" "GIF" NIL NIL NIL "BASE64" 60 NIL NIL NIL "b.gif") "RELATED" NIL NIL NIL "http://example.com/site/")"#).unwrap().1;
+        body.set_data(
+            Sequence::new(b"1").unwrap(),
+            br#"<IMG SRC=img/a.gif><link href="../../style.css"><img data-src="b.gif"><p>data.gif b.gif</p>"#.to_vec(),
+        );
+        let resolver = related(&body);
+        assert_eq!(resolver.resolve_index(b"img/a.gif"), Some(1));
+        assert_eq!(resolver.resolve_index(b"http://example.com/site/pages/img/a.gif"), Some(1));
+        assert_eq!(resolver.resolve_index(b"../../style.css"), Some(2));
+        // Named only in text and in an attribute that is not a URL.
+        assert_eq!(resolver.unreferenced, vec![3]);
+    }
+
+    #[test]
+    fn test_resolve_uri() {
+        let base = b"http://a/b/c/d;p?q".as_ref();
+        let cases: [(&[u8], &[u8]); 9] = [
+            (b"g", b"http://a/b/c/g"),
+            (b"./g", b"http://a/b/c/g"),
+            (b"g/", b"http://a/b/c/g/"),
+            (b"/g", b"http://a/g"),
+            (b"//g", b"http://g"),
+            (b"?y", b"http://a/b/c/d;p?y"),
+            (b"../..", b"http://a/"),
+            (b"../../../g", b"http://a/g"),
+            (b"cid:x@y", b"cid:x@y"),
+        ];
+        for (reference, expected) in cases {
+            assert_eq!(resolve_uri(base, reference), expected.to_vec());
+        }
+        assert_eq!(resolve_uri(b"http://a", b"g"), b"http://a/g".to_vec());
+    }
+
+    #[test]
+    fn test_not_related() {
+        let body = body_parser(br#"(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 10 1) "MIXED")"#).unwrap().1;
+        let Body::Multi(multi) = &body else { unreachable!() };
+        assert_eq!(Related::new(multi), None);
+    }
+}
@@ -1,39 +1,85 @@
 use nom::IResult;
 
+use crate::envelope::{self, Envelope};
+use crate::error::Error;
 use crate::extractor;
 pub use crate::extractor::uid_fetch_body_parser;
 use crate::parser;
 use std::collections::HashMap;
 
 /// When set_header == true, Body will be set all text that could be a header.
+/// Responses whose BODYSTRUCTURE fails to parse are left out; use
+/// `try_find_all_bodystructure_with_uid` to see why.
 pub fn find_all_bodystructure_with_uid(
     s: &[u8],
     set_header: bool,
 ) -> IResult<&[u8], HashMap<Vec<u8>, parser::Body>> {
-    let mut tmp_hashmap = HashMap::new();
     let (remain, responses) = extractor::split_multi_fetch_response_parser(s, true)?;
+    let bodies = parse_all_bodystructure(&responses, set_header)
+        .into_iter()
+        .filter_map(|(uid, body)| body.ok().map(|body| (uid, body)))
+        .collect();
+    Ok((remain, bodies))
+}
+
+/// Parse result of each UID's BODYSTRUCTURE.
+pub type BodyStructureResults = HashMap<Vec<u8>, Result<parser::Body, Error>>;
+
+/// Like `find_all_bodystructure_with_uid`, but keeps the parse error of each
+/// UID whose BODYSTRUCTURE could not be parsed.
+pub fn try_find_all_bodystructure_with_uid(
+    s: &[u8],
+    set_header: bool,
+) -> Result<(&[u8], BodyStructureResults), Error> {
+    let (remain, responses) = extractor::split_multi_fetch_response(s, true)?;
+    Ok((remain, parse_all_bodystructure(&responses, set_header)))
+}
+
+fn parse_all_bodystructure(responses: &[Vec<u8>], set_header: bool) -> BodyStructureResults {
+    let mut tmp_hashmap = HashMap::new();
     for response in responses.iter() {
         let uid = extractor::find_uid_in_response(response);
-        if uid.len() == 0 {
+        if uid.is_empty() {
             continue;
         }
         let bodystructure_text = extractor::extract_bodystructure(response);
-        if let Ok((body_text_within_parentheses, _)) =
+        if let Ok((body_text_within_parentheses, keyword)) =
             parser::head_bodystructure(&bodystructure_text)
         {
-            let body_result = parser::body_parser(body_text_within_parentheses);
-            match body_result {
-                Ok((_, mut body)) => {
+            let options = parser::ParserOptions {
+                form: parser::BodyForm::from_keyword(keyword),
+                ..Default::default()
+            };
+            let body_result = parser::parse_body(body_text_within_parentheses, &options)
+                .map(|parsed| {
+                    let mut body = parsed.body;
                     if set_header {
-                        body.set_header(delete_first_line(&response).to_vec());
+                        body.set_header(delete_first_line(response).to_vec());
                         body.set_header(b"MIME-Version: 1.0\r\n".to_vec());
                     }
-                    tmp_hashmap.insert(uid, body);
-                }
-                Err(_) => {
-                    continue;
-                }
-            }
+                    body
+                });
+            tmp_hashmap.insert(uid, body_result);
+        }
+    }
+    tmp_hashmap
+}
+
+/// Parse result of each UID's ENVELOPE.
+pub type EnvelopeResults = HashMap<Vec<u8>, Result<Envelope, Error>>;
+
+/// Finds the ENVELOPE of each FETCH response, keyed by UID. Responses
+/// without a UID or an ENVELOPE are left out.
+pub fn find_all_envelope_with_uid(s: &[u8]) -> Result<(&[u8], EnvelopeResults), Error> {
+    let (remain, responses) = extractor::split_multi_fetch_response(s, true)?;
+    let mut tmp_hashmap = HashMap::new();
+    for response in responses.iter() {
+        let uid = extractor::find_uid_in_response(response);
+        if uid.is_empty() {
+            continue;
+        }
+        if let Some(envelope_text) = extractor::extract_envelope(response) {
+            tmp_hashmap.insert(uid, envelope::parse_envelope(envelope_text));
         }
     }
     Ok((remain, tmp_hashmap))
@@ -48,10 +94,7 @@ fn delete_first_line(data: &[u8]) -> &[u8] {
 }
 
 pub fn is_fetch_all_body(s: &[u8]) -> bool {
-    match extractor::fetch_all_body_parser(s) {
-        Ok(_) => true,
-        Err(_) => false,
-    }
+    extractor::fetch_all_body_parser(s).is_ok()
 }
 
 #[cfg(test)]
@@ -88,24 +131,35 @@ mod tests {
                     content_transfer_encoding: ContentTransferEncodingHeaderField {
                         value: b"base64".to_vec(),
                     },
-                    content_size: ContentSize(Some(1188), Some(16)),
+                    octets: 1188,
+                    lines: Some(16),
+                    form: BodyForm::BodyStructure,
                     content_md5: ContentMD5HeaderField { value: None },
                     content_disposition: ContentDispositionHeaderField {
                         value: None,
                         parameters: Parameters { list: vec![] },
                     },
-                    content_language: ContentLanguageHeaderField { value: None },
+                    content_language: ContentLanguageHeaderField { value: vec![] },
                     content_location: ContentLocationHeaderField { value: None },
+                    extensions: vec![],
                     data: vec![],
                     raw_header: vec![],
                 })],
-                content_type: b"mixed".to_vec(),
+                content_type: MediaType::new(b"multipart", b"mixed"),
+                form: BodyForm::BodyStructure,
                 parameters: Parameters {
                     list: vec![Parameter {
                         attribute: b"boundary".to_vec(),
                         value: b"===============1522363357941492443==".to_vec(),
                     }],
                 },
+                content_disposition: ContentDispositionHeaderField {
+                    value: None,
+                    parameters: Parameters { list: vec![] },
+                },
+                content_language: ContentLanguageHeaderField { value: vec![] },
+                content_location: ContentLocationHeaderField { value: None },
+                extensions: vec![],
                 raw_header: vec![],
             }),
         );
@@ -133,35 +187,97 @@ mod tests {
                     content_transfer_encoding: ContentTransferEncodingHeaderField {
                         value: b"base64".to_vec(),
                     },
-                    content_size: ContentSize(Some(1188), Some(16)),
+                    octets: 1188,
+                    lines: Some(16),
+                    form: BodyForm::BodyStructure,
                     content_md5: ContentMD5HeaderField { value: None },
                     content_disposition: ContentDispositionHeaderField {
                         value: None,
                         parameters: Parameters { list: vec![] },
                     },
-                    content_language: ContentLanguageHeaderField { value: None },
+                    content_language: ContentLanguageHeaderField { value: vec![] },
                     content_location: ContentLocationHeaderField { value: None },
+                    extensions: vec![],
                     data: vec![],
                     raw_header: vec![],
                 })],
-                content_type: b"mixed".to_vec(),
+                content_type: MediaType::new(b"multipart", b"mixed"),
+                form: BodyForm::BodyStructure,
                 parameters: Parameters {
                     list: vec![Parameter {
                         attribute: b"boundary".to_vec(),
                         value: b"===============1522363357941492443==".to_vec(),
                     }],
                 },
+                content_disposition: ContentDispositionHeaderField {
+                    value: None,
+                    parameters: Parameters { list: vec![] },
+                },
+                content_language: ContentLanguageHeaderField { value: vec![] },
+                content_location: ContentLocationHeaderField { value: None },
+                extensions: vec![],
                 raw_header: b"Subject: =?utf-8?b?5L2g5aW9IDBiMGZiYjZkYmFmM2FmYmIgenFhLWVtYWls5rWL6K+V?=\r\nFrom: liutianyu@nextcloud.games\r\nTo: shenzongxu@nextcloud.games\r\nDate: Tue, 05 Dec 2023 06:16:58 -0000\r\nMIME-Version: 1.0\r\n".to_vec(),
             }),
         );
+        assert!(r3.1[b"649".as_ref()]
+            .decoded_header()
+            .starts_with("Subject: 你好 0b0fbb6dbaf3afbb zqa-email测试\r\n"));
         assert_eq!(r3, (b"Ok".as_ref(), h3));
     }
     #[test]
+    fn test_form_from_fetch_item() {
+        let text = b"* 1 FETCH (UID 10 BODYSTRUCTURE ((\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 1 1)(\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 1 1) \"MIXED\") BODY[HEADER] {2}\r\n\r\n\r\n)\r\n* 2 FETCH (UID 11 BODY (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 1 1) BODY[HEADER] {2}\r\n\r\n\r\n)\r\n";
+        let (_, h) = find_all_bodystructure_with_uid(text, false).unwrap();
+        let Body::Multi(multi) = &h[b"10".as_ref()] else {
+            panic!("expected a multipart body");
+        };
+        assert_eq!(multi.form, BodyForm::BodyStructure);
+        assert!(multi.parts.iter().all(|x| x.form() == BodyForm::BodyStructure));
+        assert_eq!(h[b"11".as_ref()].form(), BodyForm::Body);
+    }
+    #[test]
     fn test_is_fetch_all_body() {
-        assert_eq!(is_fetch_all_body(b"123 FETCH 3456 body[]"), true);
-        assert_eq!(is_fetch_all_body(b"123 UID FETCH 3456 body.peek[]"), true);
-        assert_eq!(is_fetch_all_body(b"123 UID FETCH 3456 body[1.1]"), false);
-        assert_eq!(is_fetch_all_body(b"123 UID FETCH 3456 (UID BODY[])"), true);
-        assert_eq!(is_fetch_all_body(b"123 UID FETCH 3456 (UID BODY.Peek[])"), true);
+        assert!(is_fetch_all_body(b"123 FETCH 3456 body[]"));
+        assert!(is_fetch_all_body(b"123 UID FETCH 3456 body.peek[]"));
+        assert!(!is_fetch_all_body(b"123 UID FETCH 3456 body[1.1]"));
+        assert!(is_fetch_all_body(b"123 UID FETCH 3456 (UID BODY[])"));
+        assert!(is_fetch_all_body(b"123 UID FETCH 3456 (UID BODY.Peek[])"));
+    }
+    #[test]
+    fn test_try_find_all_bodystructure_with_uid() {
+        let text = b"* 1 FETCH (UID 10 BODYSTRUCTURE (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 12 1) BODY[HEADER] {2}\r\n\r\n\r\n)\r\n* 2 FETCH (UID 11 BODYSTRUCTURE (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" twelve 1) BODY[HEADER] {2}\r\n\r\n\r\n)\r\n";
+        let (_, r) = try_find_all_bodystructure_with_uid(text, false).unwrap();
+        assert!(r[b"10".as_ref()].is_ok());
+        assert_eq!(
+            r[b"11".as_ref()].as_ref().unwrap_err(),
+            &Error::Body {
+                construct: crate::error::Construct::Octets,
+                section: vec![1],
+                offset: 35,
+                snippet: b"twelve 1)".to_vec(),
+            }
+        );
+        let (_, h) = find_all_bodystructure_with_uid(text, false).unwrap();
+        assert_eq!(h.len(), 1);
+    }
+    #[test]
+    fn test_find_all_envelope_with_uid() {
+        let text = b"* 1 FETCH (UID 10 ENVELOPE (NIL \"Hi\" ((NIL NIL \"a\" \"example.com\")) NIL NIL ((NIL NIL \"friends\" NIL)(NIL NIL \"b\" \"example.com\")(NIL NIL NIL NIL)) NIL NIL NIL NIL) BODY[HEADER] {2}\r\n\r\n\r\n)\r\n* 2 FETCH (UID 11 ENVELOPE (NIL NIL) BODY[HEADER] {2}\r\n\r\n\r\n)\r\n";
+        let (_, r) = find_all_envelope_with_uid(text).unwrap();
+        let envelope = r[b"10".as_ref()].as_ref().unwrap();
+        assert_eq!(envelope.subject, Some(b"Hi".to_vec()));
+        assert_eq!(
+            envelope.to,
+            vec![crate::envelope::AddressEntry::Group {
+                name: b"friends".to_vec(),
+                members: vec![crate::envelope::Address {
+                    name: None,
+                    adl: None,
+                    mailbox: Some(b"b".to_vec()),
+                    host: Some(b"example.com".to_vec()),
+                }],
+            }]
+        );
+        assert!(r[b"11".as_ref()].is_err());
     }
 }
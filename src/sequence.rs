@@ -1,19 +1,20 @@
-use std::{collections::VecDeque, str::from_utf8};
+use std::{collections::VecDeque, fmt, str::from_utf8};
 
 use nom::{
-    bytes::complete::tag, character::complete::digit1, combinator::map, multi::separated_list1,
+    bytes::complete::tag,
+    character::complete::digit1,
+    combinator::{map, map_res},
+    multi::separated_list1,
     IResult,
 };
 
+use crate::error::{self, Error};
+
 pub fn sequence_parser(s: &[u8]) -> IResult<&[u8], Vec<usize>> {
     map(
         separated_list1(
             tag(b"."),
-            map(digit1, |x| {
-                let d = from_utf8(x).unwrap();
-                let num = str::parse::<usize>(d).unwrap();
-                num
-            }),
+            map_res(digit1, |x| str::parse::<usize>(from_utf8(x).unwrap())),
         ),
         |x| x,
     )(s)
@@ -23,16 +24,20 @@ pub fn sequence_parser(s: &[u8]) -> IResult<&[u8], Vec<usize>> {
 pub struct Sequence(pub VecDeque<usize>);
 
 impl Sequence {
-    pub fn new(s: &[u8]) -> Result<Sequence, String> {
-        if let Ok((_, sequence)) = sequence_parser(s) {
-            if sequence.is_empty() {
-                return Err(String::from("Parsing sequence unsuccessfully."))
+    pub fn new(s: &[u8]) -> Result<Sequence, Error> {
+        match sequence_parser(s) {
+            Ok((_, sequence)) => {
+                let mut tmp: VecDeque<usize> = VecDeque::new();
+                tmp.extend(sequence);
+                Ok(Sequence(tmp))
+            }
+            Err(e) => {
+                let offset = error::nom_offset(s, &e);
+                Err(Error::Sequence {
+                    offset,
+                    snippet: error::snippet(s, offset),
+                })
             }
-            let mut tmp: VecDeque<usize> = VecDeque::new();
-            tmp.extend(sequence);
-            Ok(Sequence(tmp))
-        } else {
-            Err(String::from("Parsing sequence unsuccessfully."))
         }
     }
 
@@ -49,6 +54,19 @@ impl Sequence {
     }
 }
 
+/// Writes the section as in a FETCH item, e.g. `1.2`.
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, number) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{}", number)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,8 +89,17 @@ mod tests {
     }
     #[test]
     fn test_3() {
-        let target = String::from("Parsing sequence unsuccessfully.");
+        let target = Error::Sequence {
+            offset: 0,
+            snippet: b"HEADER".to_vec(),
+        };
         let seq = Sequence::new(b"HEADER").unwrap_err();
         assert_eq!(seq.clone(), target);
+        assert!(Sequence::new(b"99999999999999999999999").is_err());
+    }
+    #[test]
+    fn test_display() {
+        assert_eq!(Sequence::new(b"1.2.10").unwrap().to_string(), "1.2.10");
+        assert_eq!(Sequence(VecDeque::new()).to_string(), "");
     }
 }
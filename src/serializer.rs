@@ -0,0 +1,407 @@
+//! Writes a `Body` back out as RFC 3501 BODYSTRUCTURE or BODY text.
+//!
+//! Strings are quoted when they are 7-bit text and sent as literals
+//! otherwise, so for any `Body` that `body_parser` produced,
+//! `body_parser(&serialize(&body))` gives back an equal `Body`. `data` and
+//! `raw_header` are not part of the wire format and are not written.
+//!
+//! Bodies the strict parser cannot produce only round-trip as far as the
+//! wire format allows: a multipart without parts is written as such and
+//! only parses back in lenient mode, and a text part without a line count
+//! is written with 0 lines.
+//!
+//! ```rust
+//! # use imap_bodystructure::parser::body_parser;
+//! # use imap_bodystructure::serializer::serialize;
+//! let text = br#"("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "8BIT" 393 9 NIL NIL NIL NIL)"#;
+//! let body = body_parser(text).unwrap().1;
+//! assert_eq!(serialize(&body), text.to_vec());
+//! ```
+
+use crate::envelope::{Address, AddressEntry, Envelope};
+use crate::parser::{
+    escape_quoted, Body, BodyExtension, BodyForm, ContentDispositionHeaderField,
+    ContentLanguageHeaderField, MediaType, MessageBody, MultiBody, Parameters, SingleBody,
+};
+
+/// The body with each part in the form it was parsed from.
+pub fn serialize(body: &Body) -> Vec<u8> {
+    let mut result = vec![];
+    write_body(&mut result, body, None);
+    result
+}
+
+/// The body with every part in the given form; `BodyForm::Body` leaves out
+/// all extension data, e.g. to answer a BODY fetch from a stored BODYSTRUCTURE.
+pub fn serialize_with_form(body: &Body, form: BodyForm) -> Vec<u8> {
+    let mut result = vec![];
+    write_body(&mut result, body, Some(form));
+    result
+}
+
+/// The FETCH item, "BODYSTRUCTURE (...)" or "BODY (...)" after the body's form.
+pub fn serialize_fetch_item(body: &Body) -> Vec<u8> {
+    let mut result = match body.form() {
+        BodyForm::BodyStructure => b"BODYSTRUCTURE ".to_vec(),
+        BodyForm::Body => b"BODY ".to_vec(),
+    };
+    result.append(&mut serialize(body));
+    result
+}
+
+pub fn serialize_envelope(envelope: &Envelope) -> Vec<u8> {
+    let mut result = vec![];
+    write_envelope(&mut result, envelope);
+    result
+}
+
+/// A string as a quoted string, or as a literal when it holds bytes a
+/// quoted string cannot carry. NUL is not a CHAR8, so a string with one
+/// becomes a literal8 (RFC 3516).
+pub(crate) fn write_string(result: &mut Vec<u8>, s: &[u8]) {
+    if s.contains(&b'\0') {
+        result.extend_from_slice(format!("~{{{}}}\r\n", s.len()).as_bytes());
+        result.extend_from_slice(s);
+    } else if s.iter().all(|x| x.is_ascii() && !matches!(x, b'\r' | b'\n')) {
+        result.push(b'"');
+        result.append(&mut escape_quoted(s));
+        result.push(b'"');
+    } else {
+        result.extend_from_slice(format!("{{{}}}\r\n", s.len()).as_bytes());
+        result.extend_from_slice(s);
+    }
+}
+
+fn write_nstring(result: &mut Vec<u8>, s: Option<&[u8]>) {
+    match s {
+        Some(s) => write_string(result, s),
+        None => result.extend_from_slice(b"NIL"),
+    }
+}
+
+fn write_number<T: ToString>(result: &mut Vec<u8>, number: T) {
+    result.extend_from_slice(number.to_string().as_bytes());
+}
+
+fn write_parameters(result: &mut Vec<u8>, parameters: &Parameters) {
+    if parameters.is_empty() {
+        result.extend_from_slice(b"NIL");
+        return;
+    }
+    result.push(b'(');
+    for (index, param) in parameters.iter().enumerate() {
+        if index > 0 {
+            result.push(b' ');
+        }
+        write_string(result, &param.attribute);
+        result.push(b' ');
+        write_string(result, &param.value);
+    }
+    result.push(b')');
+}
+
+pub(crate) fn write_extension(result: &mut Vec<u8>, extension: &BodyExtension) {
+    match extension {
+        BodyExtension::NString(value) => write_nstring(result, value.as_deref()),
+        BodyExtension::Number(value) => write_number(result, value),
+        BodyExtension::List(list) => {
+            result.push(b'(');
+            for (index, item) in list.iter().enumerate() {
+                if index > 0 {
+                    result.push(b' ');
+                }
+                write_extension(result, item);
+            }
+            result.push(b')');
+        }
+    }
+}
+
+/// body-ext-1part / body-ext-mpart after the md5 or parameters: disposition,
+/// language, location and any further extensions.
+fn write_extension_data(
+    result: &mut Vec<u8>,
+    disposition: &ContentDispositionHeaderField,
+    language: &ContentLanguageHeaderField,
+    location: Option<&[u8]>,
+    extensions: &[BodyExtension],
+) {
+    result.push(b' ');
+    match &disposition.value {
+        Some(value) => {
+            result.push(b'(');
+            write_string(result, value);
+            result.push(b' ');
+            write_parameters(result, &disposition.parameters);
+            result.push(b')');
+        }
+        None => result.extend_from_slice(b"NIL"),
+    }
+    result.push(b' ');
+    match language.value.as_slice() {
+        [] => result.extend_from_slice(b"NIL"),
+        [value] => write_string(result, value),
+        values => {
+            result.push(b'(');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    result.push(b' ');
+                }
+                write_string(result, value);
+            }
+            result.push(b')');
+        }
+    }
+    result.push(b' ');
+    write_nstring(result, location);
+    for extension in extensions {
+        result.push(b' ');
+        write_extension(result, extension);
+    }
+}
+
+/// body-fields, preceded by the media type.
+fn write_fields(
+    result: &mut Vec<u8>,
+    media_type: &MediaType,
+    parameters: &Parameters,
+    id: Option<&[u8]>,
+    description: Option<&[u8]>,
+    encoding: &[u8],
+) {
+    write_string(result, &media_type.ttype);
+    result.push(b' ');
+    write_string(result, &media_type.subtype);
+    result.push(b' ');
+    write_parameters(result, parameters);
+    result.push(b' ');
+    write_nstring(result, id);
+    result.push(b' ');
+    write_nstring(result, description);
+    result.push(b' ');
+    write_string(result, encoding);
+}
+
+fn write_single(result: &mut Vec<u8>, body: &SingleBody, form: BodyForm) {
+    result.push(b'(');
+    write_fields(
+        result,
+        &body.content_type.ttype,
+        &body.content_type.parameters,
+        body.content_id.value.as_deref(),
+        body.content_description.value.as_deref(),
+        &body.content_transfer_encoding.value,
+    );
+    result.push(b' ');
+    write_number(result, body.octets);
+    if body.media_type().is_text() {
+        result.push(b' ');
+        write_number(result, body.lines.unwrap_or(0));
+    }
+    if form == BodyForm::BodyStructure {
+        result.push(b' ');
+        write_nstring(result, body.content_md5.value.as_deref());
+        write_extension_data(
+            result,
+            &body.content_disposition,
+            &body.content_language,
+            body.content_location.value.as_deref(),
+            &body.extensions,
+        );
+    }
+    result.push(b')');
+}
+
+fn write_message(result: &mut Vec<u8>, body: &MessageBody, form: BodyForm, inner: Option<BodyForm>) {
+    result.push(b'(');
+    write_fields(
+        result,
+        &body.content_type.ttype,
+        &body.content_type.parameters,
+        body.content_id.value.as_deref(),
+        body.content_description.value.as_deref(),
+        &body.content_transfer_encoding.value,
+    );
+    result.push(b' ');
+    write_number(result, body.octets);
+    result.push(b' ');
+    write_envelope(result, &body.envelope);
+    result.push(b' ');
+    write_body(result, &body.body, inner);
+    result.push(b' ');
+    write_number(result, body.lines);
+    if form == BodyForm::BodyStructure {
+        result.push(b' ');
+        write_nstring(result, body.content_md5.value.as_deref());
+        write_extension_data(
+            result,
+            &body.content_disposition,
+            &body.content_language,
+            body.content_location.value.as_deref(),
+            &body.extensions,
+        );
+    }
+    result.push(b')');
+}
+
+fn write_multi(result: &mut Vec<u8>, body: &MultiBody, form: BodyForm, inner: Option<BodyForm>) {
+    result.push(b'(');
+    for part in &body.parts {
+        write_body(result, part, inner);
+    }
+    if !body.parts.is_empty() {
+        result.push(b' ');
+    }
+    write_string(result, &body.content_type.subtype);
+    if form == BodyForm::BodyStructure {
+        result.push(b' ');
+        write_parameters(result, &body.parameters);
+        write_extension_data(
+            result,
+            &body.content_disposition,
+            &body.content_language,
+            body.content_location.value.as_deref(),
+            &body.extensions,
+        );
+    }
+    result.push(b')');
+}
+
+/// Writes `body` in `form`, or in the form of each part when None.
+fn write_body(result: &mut Vec<u8>, body: &Body, form: Option<BodyForm>) {
+    let own_form = form.unwrap_or(body.form());
+    match body {
+        Body::Single(body) => write_single(result, body, own_form),
+        Body::Multi(body) => write_multi(result, body, own_form, form),
+        Body::Message(body) => write_message(result, body, own_form, form),
+    }
+}
+
+fn write_address(result: &mut Vec<u8>, address: &Address) {
+    result.push(b'(');
+    write_nstring(result, address.name.as_deref());
+    result.push(b' ');
+    write_nstring(result, address.adl.as_deref());
+    result.push(b' ');
+    write_nstring(result, address.mailbox.as_deref());
+    result.push(b' ');
+    write_nstring(result, address.host.as_deref());
+    result.push(b')');
+}
+
+/// An address list, with each group written as its start marker, the
+/// members and the end marker.
+fn write_address_list(result: &mut Vec<u8>, entries: &[AddressEntry]) {
+    if entries.is_empty() {
+        result.extend_from_slice(b"NIL");
+        return;
+    }
+    result.push(b'(');
+    for entry in entries {
+        match entry {
+            AddressEntry::Mailbox(address) => write_address(result, address),
+            AddressEntry::Group { name, members } => {
+                result.extend_from_slice(b"(NIL NIL ");
+                write_string(result, name);
+                result.extend_from_slice(b" NIL)");
+                for member in members {
+                    write_address(result, member);
+                }
+                result.extend_from_slice(b"(NIL NIL NIL NIL)");
+            }
+        }
+    }
+    result.push(b')');
+}
+
+fn write_envelope(result: &mut Vec<u8>, envelope: &Envelope) {
+    result.push(b'(');
+    write_nstring(result, envelope.date.as_deref());
+    result.push(b' ');
+    write_nstring(result, envelope.subject.as_deref());
+    for list in [
+        &envelope.from,
+        &envelope.sender,
+        &envelope.reply_to,
+        &envelope.to,
+        &envelope.cc,
+        &envelope.bcc,
+    ] {
+        result.push(b' ');
+        write_address_list(result, list);
+    }
+    result.push(b' ');
+    write_nstring(result, envelope.in_reply_to.as_deref());
+    result.push(b' ');
+    write_nstring(result, envelope.message_id.as_deref());
+    result.push(b')');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::envelope_parser;
+    use crate::parser::{body_parser, body_parser_with_options, ParserOptions};
+
+    fn assert_round_trip(text: &[u8]) {
+        let body = body_parser(text).unwrap().1;
+        let serialized = serialize(&body);
+        let (remain, reparsed) = body_parser(&serialized).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(reparsed, body);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_round_trip(br#"((("text" "plain" ("charset" "GB2312") NIL NIL "base64" 84 2 NIL NIL NIL NIL)("text" "html" ("charset" "GB2312") NIL NIL "quoted-printable" 629 8 NIL NIL NIL NIL) "alternative" ("boundary" "----=_002_NextPart034528600178_=----") NIL NIL NIL)("application" "octet-stream" ("name" "FB679764.tar") NIL NIL "base64" 664200 NIL ("attachment" ("filename" "FB679764.tar")) NIL NIL) "mixed" ("boundary" "----=_001_NextPart655111288810_=----") NIL NIL NIL)"#);
+        assert_round_trip(br#"(("TEXT" "PLAIN" ("CHARSET" "US-ASCII") NIL NIL "7BIT" 1152 23)("TEXT" "PLAIN" ("CHARSET" "US-ASCII" "NAME" "cc.diff") "<960723163407.20117h@cac.washington.edu>" "Compiler diff" "BASE64" 4554 73) "MIXED")"#);
+        assert_round_trip(br#"(("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 12 1 NIL NIL NIL NIL)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 ("Tue, 05 Dec 2023 06:16:58 +0000" "Original" (("Alice" NIL "alice" "example.com")) NIL NIL ((NIL NIL "team" NIL)(NIL NIL "bob" "example.org")(NIL NIL NIL NIL)) NIL NIL NIL "<1@example.com>") ("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 8 1 NIL NIL NIL NIL) 12 NIL ("attachment" NIL) ("en" "de") NIL) "MIXED" ("BOUNDARY" "b1") NIL "fr" "http://example.com/" 1 ("x" (2 NIL))))"#);
+        assert_round_trip(b"(\"application\" \"pdf\" (\"name\" {12}\r\nr\xe9sum\xe9\r\n.pdf \"t\" \"a \\\"b\\\"\") NIL NIL \"base64\" 1024 \"md5\" NIL NIL NIL)");
+        assert_round_trip(br#"("IMAGE" "GIF" NIL "<id>" NIL "BASE64" 4554)"#);
+        // Only some parts carry extension data.
+        assert_round_trip(br#"(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1 1)("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1 1 NIL) "MIXED" NIL)"#);
+    }
+
+    #[test]
+    fn test_serialize_nul() {
+        let text = b"(\"application\" \"pdf\" (\"name\" ~{5}\r\na\0b.c) NIL NIL \"base64\" 1024 NIL NIL NIL NIL)";
+        assert_round_trip(text);
+        assert_eq!(serialize(&body_parser(text).unwrap().1), text.to_vec());
+    }
+
+    #[test]
+    fn test_serialize_outside_strict_grammar() {
+        let lenient = ParserOptions { lenient: true, ..Default::default() };
+        let text = br#"("MIXED" ("BOUNDARY" "b1") NIL NIL NIL)"#;
+        let body = body_parser_with_options(text, &lenient).unwrap().1.body;
+        assert_eq!(serialize(&body), text.to_vec());
+        assert!(body_parser(&serialize(&body)).is_err());
+        assert_eq!(body_parser_with_options(&serialize(&body), &lenient).unwrap().1.body, body);
+
+        let mut body = body_parser(br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 10 1)"#).unwrap().1;
+        let Body::Single(single) = &mut body else {
+            panic!("expected a single part");
+        };
+        single.lines = None;
+        assert_eq!(serialize(&body), br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 10 0)"#.to_vec());
+    }
+
+    #[test]
+    fn test_serialize_forms() {
+        let text = br#"(("text" "plain" NIL NIL NIL "7bit" 10 1 NIL ("inline" NIL) NIL NIL) "mixed" NIL NIL NIL NIL)"#;
+        let body = body_parser(text).unwrap().1;
+        assert_eq!(serialize(&body), text.to_vec());
+        assert_eq!(
+            serialize_with_form(&body, BodyForm::Body),
+            br#"(("text" "plain" NIL NIL NIL "7bit" 10 1) "mixed")"#.to_vec()
+        );
+        assert!(serialize_fetch_item(&body).starts_with(b"BODYSTRUCTURE (("));
+    }
+
+    #[test]
+    fn test_serialize_envelope() {
+        let text = br#"("Wed, 17 Jul 1996" "a\"b" (("Terry" NIL "gray" "example.com")) NIL NIL ((NIL NIL "undisclosed-recipients" NIL)(NIL NIL NIL NIL)) NIL NIL NIL "<1@example.com>")"#;
+        let envelope = envelope_parser(text).unwrap().1;
+        assert_eq!(serialize_envelope(&envelope), text.to_vec());
+    }
+}
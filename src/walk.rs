@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+
+use crate::parser::Body;
+use crate::sequence::Sequence;
+
+/// Depth-first iterator over a body and all its parts, in section order.
+/// The multipart body of an encapsulated message has no number of its own,
+/// so it is left out and its parts follow the message/rfc822 part.
+pub struct Parts<'a> {
+    stack: Vec<(Vec<usize>, &'a Body)>,
+}
+
+/// Section of the outermost body: none for a multipart, 1 otherwise.
+pub(crate) fn root_section(body: &Body) -> Vec<usize> {
+    match body {
+        Body::Multi(_) => vec![],
+        _ => vec![1],
+    }
+}
+
+/// Parts one level below `body`, which is at `section`.
+fn children<'a>(section: &[usize], body: &'a Body) -> Vec<(Vec<usize>, &'a Body)> {
+    let numbered = |index: usize| {
+        let mut child = section.to_vec();
+        child.push(index);
+        child
+    };
+    match body {
+        Body::Single(_) => vec![],
+        Body::Multi(multi) => multi
+            .parts
+            .iter()
+            .enumerate()
+            .map(|(index, part)| (numbered(index + 1), part))
+            .collect(),
+        Body::Message(message) => match message.body.as_ref() {
+            inner @ Body::Multi(_) => children(section, inner),
+            inner => vec![(numbered(1), inner)],
+        },
+    }
+}
+
+pub(crate) fn to_sequence(section: Vec<usize>) -> Sequence {
+    Sequence(VecDeque::from(section))
+}
+
+impl<'a> Iterator for Parts<'a> {
+    type Item = (Sequence, &'a Body);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (section, body) = self.stack.pop()?;
+        self.stack.extend(children(&section, body).into_iter().rev());
+        Some((to_sequence(section), body))
+    }
+}
+
+/// Callbacks for `Body::walk`. `enter` runs before the parts of a body
+/// and `leave` after them, so together they give the shape of the tree.
+/// As in `Parts`, the multipart body of an encapsulated message is not
+/// visited itself; it is reachable through `MessageBody::body`.
+pub trait Visitor {
+    fn enter(&mut self, _section: &Sequence, _body: &Body) {}
+
+    fn leave(&mut self, _section: &Sequence, _body: &Body) {}
+}
+
+fn walk_body<V: Visitor + ?Sized>(section: Vec<usize>, body: &Body, visitor: &mut V) {
+    let sequence = to_sequence(section.clone());
+    visitor.enter(&sequence, body);
+    for (child_section, child) in children(&section, body) {
+        walk_body(child_section, child, visitor);
+    }
+    visitor.leave(&sequence, body);
+}
+
+impl Body {
+    /// Every part with its section number, starting with this body.
+    pub fn parts(&self) -> Parts<'_> {
+        Parts {
+            stack: vec![(root_section(self), self)],
+        }
+    }
+
+    /// Walks the tree depth-first in the order of `parts`.
+    pub fn walk<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+        walk_body(root_section(self), self, visitor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::body_parser;
+
+    fn section_text(sequence: &Sequence) -> String {
+        sequence
+            .0
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(".")
+    }
+
+    #[test]
+    fn test_parts() {
+        let body = body_parser(br#"(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) (("TEXT" "PLAIN" NIL NIL NIL "7BIT" 8 1)("IMAGE" "GIF" NIL NIL NIL "BASE64" 20) "MIXED") 12)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 100 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) ("TEXT" "HTML" NIL NIL NIL "7BIT" 8 1) 5) "MIXED")"#).unwrap().1;
+        let parts: Vec<(String, String)> = body
+            .parts()
+            .map(|(section, part)| (section_text(&section), part.media_type().essence()))
+            .collect();
+        let expected = [
+            ("", "multipart/mixed"),
+            ("1", "text/plain"),
+            ("2", "message/rfc822"),
+            ("2.1", "text/plain"),
+            ("2.2", "image/gif"),
+            ("3", "message/rfc822"),
+            ("3.1", "text/html"),
+        ];
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(section, media_type)| (section.to_string(), media_type.to_string()))
+            .collect();
+        assert_eq!(parts, expected);
+        let message = body_parser(br#"("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 342 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) (("TEXT" "PLAIN" NIL NIL NIL "7BIT" 8 1)("MESSAGE" "RFC822" NIL NIL NIL "7BIT" 100 (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) ("TEXT" "HTML" NIL NIL NIL "7BIT" 8 1) 5) "MIXED") 12)"#).unwrap().1;
+        let sections: Vec<String> = message.parts().map(|(x, _)| section_text(&x)).collect();
+        assert_eq!(sections, vec!["1", "1.1", "1.2", "1.2.1"]);
+        for tree in [&body, &message] {
+            for (section, part) in tree.parts() {
+                assert_eq!(tree.part(&section), Some(part));
+            }
+        }
+        let single = body_parser(br#"("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1)"#).unwrap().1;
+        let sections: Vec<String> = single.parts().map(|(x, _)| section_text(&x)).collect();
+        assert_eq!(sections, vec!["1".to_string()]);
+    }
+
+    struct Outline(Vec<String>);
+
+    impl Visitor for Outline {
+        fn enter(&mut self, section: &Sequence, body: &Body) {
+            self.0.push(format!("<{} {}", section_text(section), body.media_type().essence()));
+        }
+
+        fn leave(&mut self, section: &Sequence, _body: &Body) {
+            self.0.push(format!(">{}", section_text(section)));
+        }
+    }
+
+    #[test]
+    fn test_walk() {
+        let body = body_parser(br#"(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 12 1)(("TEXT" "PLAIN" NIL NIL NIL "7BIT" 8 1) "ALTERNATIVE") "MIXED")"#).unwrap().1;
+        let mut outline = Outline(vec![]);
+        body.walk(&mut outline);
+        assert_eq!(
+            outline.0,
+            vec![
+                "< multipart/mixed",
+                "<1 text/plain",
+                ">1",
+                "<2 multipart/alternative",
+                "<2.1 text/plain",
+                ">2.1",
+                ">2",
+                ">",
+            ]
+        );
+    }
+}